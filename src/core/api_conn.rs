@@ -3,21 +3,21 @@
  * Copyright(c) Kintaro Ponce
  * MIT Licensed
  */
-use reqwest::{Client, StatusCode, multipart};
+use reqwest::{Certificate, Client, StatusCode, multipart};
 use std::{os::unix::fs::FileExt};
-// use std::time::Duration;
+use std::time::Duration;
 use thiserror::Error;
 use std::fs;
 use std::io::Write;
 use futures_util::StreamExt;
 use url::Url;
-use crate::utils;
+use crate::core::utils;
 use crate::core::objects;
 use tokio::{
     io::{BufReader, AsyncReadExt},
 };
 
-const CHUNK_SIZE: u64 = 2097153;
+pub const CHUNK_SIZE: u64 = 2097153;
 
 /// Errores de tu capa HTTP/cliente.
 #[derive(Debug, Error)]
@@ -39,6 +39,31 @@ pub enum ApiError {
         status: StatusCode,
         snippet: String,
     },
+
+    #[error("archivo corrupto {path}: {reason}")]
+    Integrity {
+        path: String,
+        reason: String,
+    },
+
+    #[error("error de cifrado: {0}")]
+    Crypto(String),
+
+    #[error("ruta inválida {path}: {reason}")]
+    InvalidPath {
+        path: String,
+        reason: String,
+    },
+
+    #[error("error de credenciales: {0}")]
+    Credentials(String),
+
+    #[error("nombre demasiado largo una vez cifrado {name}: {length} caracteres (máximo {max})")]
+    NameTooLong {
+        name: String,
+        length: usize,
+        max: usize,
+    },
 }
 
 /// Cliente de API con configuración (timeout, headers, etc.).
@@ -49,18 +74,26 @@ pub struct ApiClient {
 }
 
 impl ApiClient {
-    pub fn new(base_url: &str, api_key: &str) -> Result<Self, ApiError> {
-        let http = Client::builder()
-            //.timeout(Duration::from_secs(60))
-            //.connect_timeout(Duration::from_secs(5))
+    pub fn new(server: &objects::Server, api_key: &str) -> Result<Self, ApiError> {
+        let mut builder = Client::builder();
             // .user_agent("mi-app/0.1") // opcional
-            .build()?;
-        let api_key = api_key;
+        if server.timeout > 0 {
+            builder = builder.timeout(Duration::from_secs(server.timeout));
+        }
+        if server.connect_timeout > 0 {
+            builder = builder.connect_timeout(Duration::from_secs(server.connect_timeout));
+        }
+        if let Some(tls) = &server.tls {
+            if !tls.ca_cert.is_empty() {
+                builder = builder.add_root_certificate(Certificate::from_pem(&fs::read(&tls.ca_cert)?)?);
+            }
+            builder = builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+        }
 
         Ok(Self {
-            base: Url::parse(base_url)?,
+            base: Url::parse(&server.base_url)?,
             api_key: api_key.to_string(),
-            http,
+            http: builder.build()?,
         })
     }
 
@@ -144,17 +177,17 @@ impl ApiClient {
       Ok(files) 
     }
 
-    pub async fn get_file(&self, path: &str, mut file: &fs::File) -> Result<&str, ApiError> {
+    pub async fn get_file(&self, path: &str, mut file: &fs::File) -> Result<u64, ApiError> {
       let mut url = self.base.clone();
       url.path_segments_mut()
           .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
-          .extend(&["files", "list", path]);
-      let resp= self.http.get(url.to_string()+"?t="+&self.api_key).send().await.unwrap();
+          .extend(&["files", "download", path]);
+      let resp= self.http.get(url.to_string()+"?t="+&self.api_key).send().await?;
       
       // status
       let status = resp.status();
       if !status.is_success() {
-        let body = resp.text().await.unwrap();
+        let body = resp.text().await?;
         let snippet = body.chars().take(200).collect::<String>();
         return Err(ApiError::HttpStatus { status, snippet });
       }
@@ -164,12 +197,12 @@ impl ApiClient {
       let mut position: u64 = 0;
 
       while let Some(item) = stream.next().await {
-        let chunk = item.unwrap();
-        file.write_at(&chunk, position).unwrap();
+        let chunk = item?;
+        file.write_at(&chunk, position)?;
         position += chunk.len() as u64;
       }
-      file.flush().unwrap();
-      Ok("okay")
+      file.flush()?;
+      Ok(position)
     }
 
   pub async fn exists_file(&self, path: &str) -> Result<objects::ExistFile, ApiError> {
@@ -210,21 +243,29 @@ impl ApiClient {
     Ok("okay")
   }
 
-  pub async fn upload_small_file(&self, remote_path: &str, path_local: &String) -> Result<&str, ApiError> {
+  pub async fn upload_small_file(&self, remote_path: &str, path_local: &str) -> Result<&str, ApiError> {
     let mut url = self.base.clone();
     url.path_segments_mut()
         .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
         .extend(&["files", "upload", remote_path]);
-    let file = tokio::fs::File::open(&path_local).await?;
+    let file = tokio::fs::File::open(path_local).await?;
     let file_stream = multipart::Part::stream(file)
         .file_name("file")
         .mime_str("application/octet-stream").unwrap();
 
     let form = multipart::Form::new().part("file", file_stream);
       
-    let _ = self.http.post(url.to_string()+"?t="+&self.api_key).multipart(form).send().await.unwrap(); 
+    let resp = self.http.post(url.to_string()+"?t="+&self.api_key).multipart(form).send().await?;
 
-    return Ok("okay")
+    // status
+    let status = resp.status();
+    if !status.is_success() {
+      let body = resp.text().await?;
+      let snippet = body.chars().take(200).collect::<String>();
+      return Err(ApiError::HttpStatus { status, snippet });
+    }
+
+    Ok("okay")
   }
 
   pub async fn initialize_file(&self, path: &str, size: u64) -> Result<&str, ApiError> {
@@ -249,21 +290,98 @@ impl ApiClient {
     Ok("okay")
   }
 
-  pub async fn upload_file_chunks(&self, remote_path: &str, path_local: &String, size: u64, virtual_path: &String) -> Result<&str, ApiError> {
+  pub async fn write_chunk(&self, remote_path: &str, position: u64, buffer: Vec<u8>) -> Result<&str, ApiError> {
     let mut url = self.base.clone();
     url.path_segments_mut()
         .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
         .extend(&["files", "write", remote_path]);
-    let file = tokio::fs::File::open(&path_local).await?;
+
+    let part = multipart::Part::stream(buffer)
+      .file_name("file")
+      .mime_str("application/octet-stream").unwrap();
+
+    let form = multipart::Form::new().part("file", part);
+
+    let resp= self.http.post(url.to_string()+"?t="+&self.api_key+"&pos="+&position.to_string()).multipart(form).send().await?;
+
+    // status
+    let status = resp.status();
+    if !status.is_success() {
+      let body = resp.text().await?;
+      let snippet = body.chars().take(200).collect::<String>();
+      return Err(ApiError::HttpStatus { status, snippet });
+    }
+
+    Ok("okay")
+  }
+
+  pub async fn delete_file(&self, path: &str) -> Result<&str, ApiError> {
+    let mut url = self.base.clone();
+    url.path_segments_mut()
+        .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
+        .extend(&["files", "delete", path]);
+    let resp = self.http.delete(url.to_string()+"?t="+&self.api_key).send().await?;
+
+    // status
+    let status = resp.status();
+    if !status.is_success() {
+      let body = resp.text().await?;
+      let snippet = body.chars().take(200).collect::<String>();
+      return Err(ApiError::HttpStatus { status, snippet });
+    }
+
+    Ok("okay")
+  }
+
+  pub async fn move_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    let mut url = self.base.clone();
+    url.path_segments_mut()
+        .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
+        .extend(&["files", "move", path]);
+
+    let destination_body = objects::DestinationBody { destination: destination.to_string() };
+    let resp = self.http.post(url.to_string()+"?t="+&self.api_key).json(&destination_body).send().await?;
+
+    // status
+    let status = resp.status();
+    if !status.is_success() {
+      let body = resp.text().await?;
+      let snippet = body.chars().take(200).collect::<String>();
+      return Err(ApiError::HttpStatus { status, snippet });
+    }
+
+    Ok("okay")
+  }
+
+  pub async fn copy_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    let mut url = self.base.clone();
+    url.path_segments_mut()
+        .map_err(|_| url::ParseError::SetHostOnCannotBeABaseUrl)?
+        .extend(&["files", "copy", path]);
+
+    let destination_body = objects::DestinationBody { destination: destination.to_string() };
+    let resp = self.http.post(url.to_string()+"?t="+&self.api_key).json(&destination_body).send().await?;
+
+    // status
+    let status = resp.status();
+    if !status.is_success() {
+      let body = resp.text().await?;
+      let snippet = body.chars().take(200).collect::<String>();
+      return Err(ApiError::HttpStatus { status, snippet });
+    }
+
+    Ok("okay")
+  }
+
+  pub async fn upload_file_chunks(&self, remote_path: &str, path_local: &str, size: u64, virtual_path: &str) -> Result<&str, ApiError> {
+    let file = tokio::fs::File::open(path_local).await?;
     let mut reader = BufReader::with_capacity(CHUNK_SIZE as usize, file);
     let mut percentage: f32 = 0.0;
     let mut offset: u64 = 0;
 
-    println!("Uploading {} ({}%)",virtual_path,format!("{}",percentage));
+    crate::detail!("Uploading {} ({}%)",virtual_path,percentage);
 
     loop {
-      let position_str: String = format!("{}", offset);
-
       let mut buffer = vec![0u8; CHUNK_SIZE as usize];
       if size > CHUNK_SIZE && size - offset < CHUNK_SIZE {
         buffer = vec![0u8; (size - offset) as usize];
@@ -276,27 +394,14 @@ impl ApiClient {
       if bytes_read == 0 {
           break; // EOF
       }
+      buffer.truncate(bytes_read);
 
-      let part = multipart::Part::stream(buffer)
-        .file_name("file")
-        .mime_str("application/octet-stream").unwrap();
-
-      let form = multipart::Form::new().part("file", part);
-      
-      let resp= self.http.post(url.to_string()+"?t="+&self.api_key+"&pos="+&position_str).multipart(form).send().await.unwrap();  
+      self.write_chunk(remote_path, offset, buffer).await?;
 
       offset += bytes_read as u64;
 
       percentage = utils::calc_file_uploaded(offset, size);
-      println!("Uploading {} ({}%)",virtual_path,format!("{}",percentage));
-      // status
-      let status = resp.status();
-      if !status.is_success() {
-        let body = resp.text().await.unwrap();
-        let snippet = body.chars().take(200).collect::<String>();
-        return Err(ApiError::HttpStatus { status, snippet });
-      }
-
+      crate::detail!("Uploading {} ({}%)",virtual_path,percentage);
     }
 
     Ok("okay")
@@ -0,0 +1,382 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::core::api_conn::ApiError;
+use crate::core::engine::{self, SyncContext};
+use crate::core::objects::{Dirsync, EntryType, FileStat, Retention};
+use crate::core::remote::Remote;
+use crate::core::{file_conn, sync_state, utils};
+
+const MANIFEST_NAME: &str = ".kcloud-manifest.json";
+pub const STAMP_FORMAT: &str = "%Y-%m-%dT%H-%M";
+
+/// Everything a snapshot holds, files unchanged since an older snapshot point to the one storing them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+  pub snapshot: String,
+  #[serde(default)]
+  pub folders: Vec<String>,
+  #[serde(default)]
+  pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestEntry {
+  pub size: u64,
+  pub mtime: i64,
+  pub mtime_nsec: i64,
+  #[serde(default)]
+  pub hash: Option<String>,
+  pub remote_size: u64,
+  pub stored_in: String,
+}
+
+fn snapshot_date(stamp: &str) -> Option<NaiveDate> {
+  NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).ok().map(|stamp| stamp.date())
+}
+
+pub fn snapshot_path(dir: &Dirsync, stamp: &str) -> String {
+  utils::create_path(&dir.remote_path, stamp)
+}
+
+/// Snapshot folders of `dir`, oldest first.
+pub async fn list_snapshots(dir: &Dirsync, remote: &Remote) -> Result<Vec<String>, ApiError> {
+  if !remote.exists_file(&dir.remote_path).await?.exists {
+    return Ok(Vec::new());
+  }
+  let mut snapshots: Vec<String> = remote.get_files_list(&dir.remote_path).await?.list
+    .into_iter()
+    .filter(|file| file.r#type == EntryType::Folder && snapshot_date(&file.name).is_some())
+    .map(|file| file.name)
+    .collect();
+  snapshots.sort();
+  Ok(snapshots)
+}
+
+/// Downloads the manifest of a snapshot, `None` for snapshots that were interrupted before writing it.
+pub async fn load_manifest(ctx: &mut SyncContext<'_>, stamp: &str) -> Result<Option<Manifest>, ApiError> {
+  let remote_path = utils::create_path(&snapshot_path(ctx.dir, stamp), MANIFEST_NAME);
+  if !ctx.remote.exists_file(&remote_path).await?.exists {
+    return Ok(None);
+  }
+
+  // under the state dir, verify and status must not write into local_path
+  let temp_path = sync_state::temp_file("manifest")?;
+  let received = engine::receive_file(ctx, &remote_path, &temp_path).await;
+  let raw_json = received.and_then(|_| Ok(fs::read_to_string(&temp_path)?));
+  let _ = file_conn::remove_file(&temp_path);
+  Ok(Some(serde_json::from_str(&raw_json?)?))
+}
+
+async fn save_manifest(ctx: &mut SyncContext<'_>, manifest: &Manifest) -> Result<(), ApiError> {
+  let remote_path = utils::create_path(&snapshot_path(ctx.dir, &manifest.snapshot), MANIFEST_NAME);
+  let temp_path = sync_state::temp_file("manifest")?;
+  fs::write(&temp_path, serde_json::to_string(manifest)?)?;
+
+  let size = file_conn::get_file_size(&temp_path)?;
+  let result = engine::send_file(ctx, &temp_path, &remote_path, size, MANIFEST_NAME).await;
+  let _ = file_conn::remove_file(&temp_path);
+  result.map(|_| ())
+}
+
+pub fn collect_local(ctx: &SyncContext<'_>, virtual_path: &str, folders: &mut Vec<String>, files: &mut Vec<(String, FileStat)>) -> Result<(), ApiError> {
+  let mut names = file_conn::file_list(&ctx.local_path(virtual_path));
+  names.sort();
+
+  for name in names.iter().filter(|name| !utils::is_reserved_name(name)) {
+    let item_path = utils::create_path(virtual_path, name);
+    let stat = file_conn::get_file_stat(&ctx.local_path(&item_path))?;
+    if stat.is_dir {
+      folders.push(item_path.clone());
+      collect_local(ctx, &item_path, folders, files)?;
+    } else {
+      files.push((item_path, stat));
+    }
+  }
+  Ok(())
+}
+
+/// Uploads a file into the current snapshot unless the previous one already has the same content.
+/// Returns its manifest entry and whether it was uploaded.
+async fn backup_file(ctx: &mut SyncContext<'_>, stamp: &str, previous: &Manifest, virtual_path: &str, stat: &FileStat) -> Result<(ManifestEntry, bool), ApiError> {
+  let hash = ctx.local_hash(virtual_path, stat)?;
+
+  if let Some(entry) = previous.files.get(virtual_path) {
+    let hash_matches = match (&entry.hash, &hash) {
+      (Some(old), Some(new)) => old == new,
+      _ => true,
+    };
+    if entry.size == stat.size && entry.mtime == stat.mtime && entry.mtime_nsec == stat.mtime_nsec && hash_matches {
+      return Ok((ManifestEntry { hash, ..entry.clone() }, false));
+    }
+  }
+
+  let local_path = ctx.local_path(virtual_path);
+  let remote_path = ctx.remote_path(virtual_path)?;
+  crate::detail!("uploading {}", virtual_path);
+  let remote_size = engine::send_file(ctx, &local_path, &remote_path, stat.size, virtual_path).await?;
+  crate::progress!("uploaded  {}", virtual_path);
+
+  Ok((ManifestEntry {
+    size: stat.size,
+    mtime: stat.mtime,
+    mtime_nsec: stat.mtime_nsec,
+    hash,
+    remote_size,
+    stored_in: stamp.to_string(),
+  }, true))
+}
+
+/// Files the next snapshot would upload, compared with the latest one by size and mtime.
+/// Returns the latest snapshot with the paths and total bytes of those files.
+pub async fn pending(ctx: &mut SyncContext<'_>) -> Result<(Option<String>, Vec<String>, u64), ApiError> {
+  let latest = list_snapshots(ctx.dir, ctx.remote).await?.pop();
+  let previous = match &latest {
+    Some(stamp) => load_manifest(ctx, stamp).await?.unwrap_or_default(),
+    None => Manifest::default(),
+  };
+
+  let mut folders: Vec<String> = Vec::new();
+  let mut files: Vec<(String, FileStat)> = Vec::new();
+  if file_conn::file_exists(&ctx.dir.local_path) {
+    collect_local(ctx, "", &mut folders, &mut files)?;
+  }
+
+  let mut changed: Vec<String> = Vec::new();
+  let mut bytes: u64 = 0;
+  for (virtual_path, stat) in files.into_iter() {
+    let unchanged = previous.files.get(&virtual_path)
+      .is_some_and(|entry| entry.size == stat.size && entry.mtime == stat.mtime && entry.mtime_nsec == stat.mtime_nsec);
+    if !unchanged {
+      changed.push(virtual_path);
+      bytes += stat.size;
+    }
+  }
+  Ok((latest, changed, bytes))
+}
+
+/// Lists what the next snapshot of `dir` would upload, without creating it.
+pub async fn backup_dry_run(dir: &Dirsync, remote: &Remote) -> Result<String, Box<dyn std::error::Error>> {
+  let mut ctx = SyncContext::new(dir, remote)?;
+  ctx.dry_run = true;
+  let (latest, changed, bytes) = pending(&mut ctx).await?;
+  for virtual_path in changed.iter() {
+    crate::progress!("would upload {}", virtual_path);
+  }
+  let kept = match latest {
+    Some(stamp) => format!("the rest is kept from snapshot {}", stamp),
+    None => String::from("no snapshot exists yet"),
+  };
+  Ok(format!("dry run: a new snapshot of {} would upload {} files ({}), {}", dir.local_path, changed.len(), utils::format_bytes(bytes), kept))
+}
+
+/// Snapshots `retention` keeps, the newest one is always kept.
+fn kept_snapshots(snapshots: &[String], retention: &Retention, today: NaiveDate) -> HashSet<String> {
+  let mut kept: HashSet<String> = HashSet::new();
+  let mut days: HashSet<NaiveDate> = HashSet::new();
+  let mut weeks: HashSet<(i32, u32)> = HashSet::new();
+  let mut months: HashSet<(i32, u32)> = HashSet::new();
+
+  for (index, stamp) in snapshots.iter().rev().enumerate() {
+    let date = match snapshot_date(stamp) {
+      Some(date) => date,
+      None => continue,
+    };
+    let days_old = (today - date).num_days();
+    let months_old = (today.year() * 12 + today.month() as i32) - (date.year() * 12 + date.month() as i32);
+    let week = (date.iso_week().year(), date.iso_week().week());
+
+    // `|` doesn't short-circuit, every rule has to see every snapshot to claim the newest one of its periods
+    let keep = (index == 0)
+      | (days_old < retention.daily as i64 && days.insert(date))
+      | (days_old < retention.weekly as i64 * 7 && weeks.insert(week))
+      | (months_old < retention.monthly as i32 && months.insert((date.year(), date.month())));
+    if keep {
+      kept.insert(stamp.clone());
+    }
+  }
+  kept
+}
+
+/// Deletes the snapshots `retention` doesn't keep. Files that kept snapshots still point to
+/// are copied to the oldest of them and their manifests are updated first, so an interrupted
+/// run never leaves a manifest pointing to a deleted file.
+async fn apply_retention(ctx: &mut SyncContext<'_>, retention: &Retention) -> Result<(), ApiError> {
+  let snapshots = list_snapshots(ctx.dir, ctx.remote).await?;
+  let kept = kept_snapshots(&snapshots, retention, Local::now().date_naive());
+  let expired: Vec<&String> = snapshots.iter().filter(|stamp| !kept.contains(*stamp)).collect();
+  if expired.is_empty() {
+    return Ok(());
+  }
+
+  let mut manifests: Vec<Manifest> = Vec::new();
+  for stamp in snapshots.iter().filter(|stamp| kept.contains(*stamp)) {
+    if let Some(manifest) = load_manifest(ctx, stamp).await? {
+      manifests.push(manifest);
+    }
+  }
+
+  for stamp in expired {
+    let mut changed: HashSet<usize> = HashSet::new();
+
+    for index in 0..manifests.len() {
+      let referenced: Vec<(String, u64)> = manifests[index].files.iter()
+        .filter(|(_, entry)| &entry.stored_in == stamp)
+        .map(|(path, entry)| (path.clone(), entry.remote_size))
+        .collect();
+      let new_home = manifests[index].snapshot.clone();
+
+      for (virtual_path, remote_size) in referenced {
+        let from = ctx.remote_path_in(&snapshot_path(ctx.dir, stamp), &virtual_path)?;
+        let to = ctx.remote_path_in(&snapshot_path(ctx.dir, &new_home), &virtual_path)?;
+        // an interrupted run may have copied it already, the snapshot doesn't store that path itself
+        if ctx.remote.exists_file(&to).await?.exists {
+          if ctx.remote.get_file_properties(&to).await?.size != Some(remote_size) {
+            ctx.remote.delete_file(&to).await?;
+            ctx.remote.copy_file(&from, &to).await?;
+          }
+        } else {
+          ctx.remote.copy_file(&from, &to).await?;
+        }
+
+        for (later, manifest) in manifests.iter_mut().enumerate().skip(index) {
+          if let Some(entry) = manifest.files.get_mut(&virtual_path) && &entry.stored_in == stamp {
+            entry.stored_in = new_home.clone();
+            changed.insert(later);
+          }
+        }
+      }
+    }
+
+    for index in changed {
+      save_manifest(ctx, &manifests[index]).await?;
+    }
+    ctx.remote.delete_file(&snapshot_path(ctx.dir, stamp)).await?;
+    crate::progress!("removed snapshot {}", stamp);
+  }
+  Ok(())
+}
+
+/// Uploads `dir.local_path` into a new snapshot folder under `dir.remote_path`.
+pub async fn backup_files(dir: &Dirsync, remote: &Remote) -> Result<String, Box<dyn std::error::Error>> {
+  if !file_conn::file_exists(&dir.local_path) {
+    return Ok(format!("{} does not exist, nothing to back up", dir.local_path));
+  }
+
+  let mut ctx = SyncContext::new(dir, remote)?;
+  let snapshots = list_snapshots(dir, remote).await?;
+  let stamp = Local::now().format(STAMP_FORMAT).to_string();
+  if snapshots.contains(&stamp) {
+    return Ok(format!("snapshot {} already exists", stamp));
+  }
+  let previous = match snapshots.last() {
+    Some(last) => load_manifest(&mut ctx, last).await?.unwrap_or_default(),
+    None => Manifest::default(),
+  };
+
+  let mut folders: Vec<String> = Vec::new();
+  let mut files: Vec<(String, FileStat)> = Vec::new();
+  collect_local(&ctx, "", &mut folders, &mut files)?;
+
+  ctx.remote_root = snapshot_path(dir, &stamp);
+  remote.create_folder(&ctx.remote_root).await?;
+  for folder in folders.iter() {
+    let remote_path = ctx.remote_path(folder)?;
+    remote.create_folder(&remote_path).await?;
+  }
+
+  let mut manifest = Manifest { snapshot: stamp.clone(), folders, files: BTreeMap::new() };
+  let mut uploaded: u32 = 0;
+  let mut failed: u32 = 0;
+  for (virtual_path, stat) in files.iter() {
+    match backup_file(&mut ctx, &stamp, &previous, virtual_path, stat).await {
+      Ok((entry, sent)) => {
+        if sent {
+          uploaded += 1;
+        }
+        manifest.files.insert(virtual_path.clone(), entry);
+      },
+      Err(err) => {
+        eprintln!("Error: {} ({})", err, virtual_path);
+        failed += 1;
+      },
+    }
+  }
+
+  // failed files are left out of the manifest, so the next snapshot uploads them again
+  save_manifest(&mut ctx, &manifest).await?;
+  if let Some(retention) = &dir.retention {
+    apply_retention(&mut ctx, retention).await?;
+  }
+  if failed == 0 {
+    ctx.state.last_sync = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+    ctx.hashes.prune();
+  }
+  ctx.save()?;
+
+  let unchanged = manifest.files.len() as u32 - uploaded;
+  if failed > 0 {
+    return Ok(format!("snapshot {} finished with {} errors ({} uploaded, {} unchanged)", stamp, failed, uploaded, unchanged));
+  }
+  Ok(format!("snapshot {} finished ({} uploaded, {} unchanged)", stamp, uploaded, unchanged))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn retention(daily: u32, weekly: u32, monthly: u32) -> Retention {
+    Retention { daily, weekly, monthly }
+  }
+
+  fn today() -> NaiveDate {
+    // a monday, the day before is the last one of the previous iso week
+    NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+  }
+
+  fn kept(snapshots: &[&str], retention: &Retention) -> Vec<String> {
+    let snapshots: Vec<String> = snapshots.iter().map(|stamp| stamp.to_string()).collect();
+    let kept = kept_snapshots(&snapshots, retention, today());
+    snapshots.into_iter().filter(|stamp| kept.contains(stamp)).collect()
+  }
+
+  #[test]
+  fn newest_snapshot_is_always_kept() {
+    let snapshots = ["2025-01-01T10-00", "2025-02-01T10-00"];
+    assert_eq!(kept(&snapshots, &retention(0, 0, 0)), ["2025-02-01T10-00"]);
+  }
+
+  #[test]
+  fn daily_keeps_the_newest_snapshot_of_each_day_in_the_window() {
+    let snapshots = ["2026-10-17T20-00", "2026-10-18T08-00", "2026-10-18T20-00", "2026-10-19T08-00"];
+    assert_eq!(kept(&snapshots, &retention(2, 0, 0)), ["2026-10-18T20-00", "2026-10-19T08-00"]);
+    assert_eq!(kept(&snapshots, &retention(3, 0, 0)), ["2026-10-17T20-00", "2026-10-18T20-00", "2026-10-19T08-00"]);
+  }
+
+  #[test]
+  fn weekly_keeps_the_newest_snapshot_of_each_iso_week_in_the_window() {
+    let snapshots = ["2026-10-04T08-00", "2026-10-05T08-00", "2026-10-11T08-00", "2026-10-12T08-00", "2026-10-18T08-00", "2026-10-19T08-00"];
+    // the 4th is 15 days old, out of a window of two weeks, and the 5th shares its week with the 11th
+    assert_eq!(kept(&snapshots, &retention(0, 2, 0)), ["2026-10-11T08-00", "2026-10-18T08-00", "2026-10-19T08-00"]);
+    assert_eq!(kept(&snapshots, &retention(0, 3, 0)), ["2026-10-04T08-00", "2026-10-11T08-00", "2026-10-18T08-00", "2026-10-19T08-00"]);
+  }
+
+  #[test]
+  fn monthly_keeps_the_newest_snapshot_of_each_month_in_the_window() {
+    let snapshots = ["2026-08-31T08-00", "2026-09-01T08-00", "2026-09-30T08-00", "2026-10-01T08-00"];
+    assert_eq!(kept(&snapshots, &retention(0, 0, 2)), ["2026-09-30T08-00", "2026-10-01T08-00"]);
+  }
+
+  #[test]
+  fn rules_add_up() {
+    let snapshots = ["2026-08-31T08-00", "2026-10-11T08-00", "2026-10-18T08-00", "2026-10-19T08-00"];
+    assert_eq!(kept(&snapshots, &retention(1, 2, 3)), snapshots);
+  }
+}
@@ -0,0 +1,152 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use async_recursion::async_recursion;
+use serde::Serialize;
+use crate::core::api_conn::ApiError;
+use crate::core::engine::SyncContext;
+use crate::core::objects::{EntryType, File, FileProperties};
+use crate::core::utils;
+
+fn is_folder(file: &File) -> bool {
+  file.r#type == EntryType::Folder
+}
+
+/// Folders first, then files, both by name.
+async fn sorted_list(ctx: &mut SyncContext<'_>, virtual_path: &str) -> Result<Vec<File>, ApiError> {
+  let mut list = ctx.remote_list(virtual_path).await?;
+  list.sort_by(|a, b| is_folder(b).cmp(&is_folder(a)).then(a.name.cmp(&b.name)));
+  Ok(list)
+}
+
+fn format_size(size: Option<u64>) -> String {
+  size.map(|size| size.to_string()).unwrap_or(String::from("-"))
+}
+
+pub async fn ls(ctx: &mut SyncContext<'_>, virtual_path: &str) -> Result<Vec<File>, ApiError> {
+  sorted_list(ctx, virtual_path).await
+}
+
+pub fn display_ls(list: &[File], long: bool) {
+  for file in list.iter() {
+    let name = if is_folder(file) { format!("{}/", file.name) } else { file.name.clone() };
+    if long {
+      println!("{:<6} {:>12} {:<16} {}", file.r#type, format_size(file.size), utils::format_modified(&file.modified), name);
+    } else {
+      println!("{}", name);
+    }
+  }
+}
+
+#[derive(Serialize)]
+pub struct TreeNode {
+  pub name: String,
+  pub r#type: EntryType,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub children: Vec<TreeNode>,
+}
+
+#[async_recursion]
+async fn tree_level(ctx: &mut SyncContext<'_>, virtual_path: &str, level: u32, depth: Option<u32>) -> Result<Vec<TreeNode>, ApiError> {
+  let mut nodes: Vec<TreeNode> = Vec::new();
+  for file in sorted_list(ctx, virtual_path).await? {
+    let children = match is_folder(&file) && depth.is_none_or(|depth| level < depth) {
+      true => tree_level(ctx, &utils::create_path(virtual_path, &file.name), level + 1, depth).await?,
+      false => Vec::new(),
+    };
+    nodes.push(TreeNode { name: file.name, r#type: file.r#type, children });
+  }
+  Ok(nodes)
+}
+
+/// Folder tree below `virtual_path`, `depth` levels deep, everything without one.
+pub async fn tree(ctx: &mut SyncContext<'_>, virtual_path: &str, depth: Option<u32>) -> Result<TreeNode, ApiError> {
+  let children = tree_level(ctx, virtual_path, 1, depth).await?;
+  let name = if virtual_path.is_empty() { String::from(".") } else { virtual_path.to_string() };
+  Ok(TreeNode { name, r#type: EntryType::Folder, children })
+}
+
+fn display_tree_level(nodes: &[TreeNode], prefix: &str) {
+  for (index, node) in nodes.iter().enumerate() {
+    let last = index == nodes.len() - 1;
+    let branch = if last { "└── " } else { "├── " };
+    let name = if node.r#type == EntryType::Folder { format!("{}/", node.name) } else { node.name.clone() };
+    println!("{}{}{}", prefix, branch, name);
+    display_tree_level(&node.children, &format!("{}{}", prefix, if last { "    " } else { "│   " }));
+  }
+}
+
+pub fn display_tree(root: &TreeNode) {
+  println!("{}", root.name);
+  display_tree_level(&root.children, "");
+}
+
+#[derive(Serialize)]
+pub struct Stat {
+  pub path: String,
+  pub remote_path: String,
+  #[serde(flatten)]
+  pub properties: FileProperties,
+}
+
+pub async fn stat(ctx: &mut SyncContext<'_>, virtual_path: &str) -> Result<Stat, ApiError> {
+  let remote_path = ctx.remote_path(virtual_path)?;
+  let properties = ctx.remote.get_file_properties(&remote_path).await?;
+  Ok(Stat { path: virtual_path.to_string(), remote_path, properties })
+}
+
+pub fn display_stat(stat: &Stat) {
+  let properties = &stat.properties;
+  let field = |value: &Option<String>| value.clone().unwrap_or(String::from("-"));
+
+  println!("path:      {}", stat.path);
+  if stat.remote_path != stat.path {
+    println!("remote:    {}", stat.remote_path);
+  }
+  println!("name:      {}", field(&properties.name));
+  println!("type:      {}", properties.r#type);
+  println!("size:      {}", format_size(properties.size));
+  println!("modified:  {}", utils::format_modified(&properties.modified));
+  println!("extension: {}", field(&properties.extension));
+  println!("mime type: {}", field(&properties.mime_type));
+  println!("sha256:    {}", field(&properties.sha256));
+}
+
+#[derive(Serialize)]
+pub struct DiskUsage {
+  pub path: String,
+  pub size: u64,
+}
+
+/// Adds up the size of every file below `virtual_path`, keeping the totals of the folders
+/// up to `depth` levels deep, the deepest ones first like `du -d`.
+#[async_recursion]
+async fn du_level(ctx: &mut SyncContext<'_>, virtual_path: &str, level: u32, depth: Option<u32>, usage: &mut Vec<DiskUsage>) -> Result<u64, ApiError> {
+  let mut total: u64 = 0;
+  for file in sorted_list(ctx, virtual_path).await?.iter() {
+    if is_folder(file) {
+      total += du_level(ctx, &utils::create_path(virtual_path, &file.name), level + 1, depth, usage).await?;
+    } else {
+      total += file.size.unwrap_or(0);
+    }
+  }
+  if depth.is_none_or(|depth| level <= depth) {
+    let path = if virtual_path.is_empty() { String::from(".") } else { virtual_path.to_string() };
+    usage.push(DiskUsage { path, size: total });
+  }
+  Ok(total)
+}
+
+pub async fn du(ctx: &mut SyncContext<'_>, virtual_path: &str, depth: Option<u32>) -> Result<Vec<DiskUsage>, ApiError> {
+  let mut usage: Vec<DiskUsage> = Vec::new();
+  du_level(ctx, virtual_path, 0, depth, &mut usage).await?;
+  Ok(usage)
+}
+
+pub fn display_du(usage: &[DiskUsage]) {
+  for entry in usage.iter() {
+    println!("{:>10}  {}", utils::format_bytes(entry.size), entry.path);
+  }
+}
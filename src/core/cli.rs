@@ -0,0 +1,305 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use clap::{Args, Parser, Subcommand};
+use crate::core::{config_file, output};
+
+pub const VERSION: &str = include_str!("../version.txt");
+
+/// Synchronizes local folders with a k-cloud server, or with another local folder.
+#[derive(Parser)]
+#[command(name = "k-cloud-sync-rust", version = VERSION, arg_required_else_help = true)]
+pub struct Cli {
+  /// Config file, config.json, .toml or .yaml in the working dir or in ~/.config/k-cloud-sync when not given
+  #[arg(short, long, global = true, env = "KCLOUD_SYNC_CONFIG", value_name = "FILE")]
+  pub config: Option<String>,
+  /// Format of the config file, taken from its extension when not given
+  #[arg(long, global = true, value_name = "FORMAT", value_parser = config_file::FORMATS)]
+  pub config_format: Option<String>,
+  /// Also print every file looked at, transfer progress and connection details
+  #[arg(short, long, global = true, conflicts_with = "quiet")]
+  pub verbose: bool,
+  /// Only print results, summaries and errors
+  #[arg(short, long, global = true)]
+  pub quiet: bool,
+  /// Show what would be done without changing anything
+  #[arg(short = 'n', long, global = true)]
+  pub dry_run: bool,
+  /// Output format of the commands that report something
+  #[arg(short, long, global = true, default_value = "text", value_parser = ["text", "json"])]
+  pub output: String,
+  #[command(subcommand)]
+  pub command: Command,
+}
+
+impl Cli {
+  pub fn config_path(&self) -> String {
+    match &self.config {
+      Some(config) => config.clone(),
+      None => config_file::default_config_path(),
+    }
+  }
+
+  pub fn config_format(&self, config_path: &str) -> String {
+    match &self.config_format {
+      Some(format) => format.clone(),
+      None => config_file::format_of(config_path).to_string(),
+    }
+  }
+
+  pub fn json(&self) -> bool {
+    self.output == "json"
+  }
+
+  /// JSON output keeps stdout for the document, the per file lines are left out.
+  pub fn verbosity(&self) -> u8 {
+    match (self.quiet || self.json(), self.verbose) {
+      (true, _) => output::QUIET,
+      (false, true) => output::VERBOSE,
+      (false, false) => output::NORMAL,
+    }
+  }
+}
+
+/// Dirs a command runs on, every dir when none is picked.
+#[derive(Args)]
+pub struct DirSelection {
+  /// Dir name, name pattern with `*` and `?`, or index in the config file, can be repeated
+  #[arg(short, long, value_name = "NAME")]
+  pub dir: Vec<String>,
+  /// Every dir with this tag, can be repeated
+  #[arg(short, long, value_name = "TAG")]
+  pub tag: Vec<String>,
+}
+
+/// Dir the remote paths of a command are relative to.
+#[derive(Args)]
+pub struct PathDir {
+  /// Name or index of the dir the remote paths are relative to, using its encryption settings.
+  /// Without one they are plain paths on the server
+  #[arg(short, long, value_name = "NAME")]
+  pub dir: Option<String>,
+  /// Server of the config file the plain paths are on, the top level one when not given
+  #[arg(short, long, value_name = "NAME", conflicts_with = "dir")]
+  pub server: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// Write a new config file, asking for the server and the folders to sync
+  Init {
+    /// Server url, like https://cloud.example.com/api or file:///mnt/backup
+    #[arg(long)]
+    url: Option<String>,
+    /// Api key, written in the config file
+    #[arg(long, value_name = "KEY", conflicts_with = "api_key_env")]
+    api_key: Option<String>,
+    /// Environment variable the api key is read from, written in the config file instead of the key
+    #[arg(long, value_name = "VARIABLE")]
+    api_key_env: Option<String>,
+    /// Folder to sync, with its sync mode: send, get, bidirectional or backup. Can be repeated
+    #[arg(long, num_args = 3, value_names = ["LOCAL", "REMOTE", "MODE"])]
+    add_dir: Vec<String>,
+    /// Don't connect to the server to check the url and the api key
+    #[arg(long)]
+    no_verify: bool,
+    /// Take everything from the options and never ask
+    #[arg(long)]
+    non_interactive: bool,
+    /// Replace the config file when it exists
+    #[arg(short, long)]
+    force: bool,
+  },
+  /// Work with the config file
+  Config {
+    #[command(subcommand)]
+    action: ConfigAction,
+  },
+  /// Synchronize the dirs
+  Sync {
+    #[command(flatten)]
+    dirs: DirSelection,
+  },
+  /// Show the dirs of the config file
+  List,
+  /// Show the local versions kept
+  Versions {
+    #[command(flatten)]
+    dirs: DirSelection,
+    /// Only the versions of this file or folder
+    path: Option<String>,
+  },
+  /// Put a local version back in place
+  RestoreVersion {
+    /// Name or index of the dir the file belongs to
+    #[arg(short, long, value_name = "NAME")]
+    dir: String,
+    /// File to restore, relative to the dir
+    path: String,
+    /// Version stamp, the newest one when not given
+    version: Option<String>,
+  },
+  /// Delete the remote trash folders
+  PurgeTrash {
+    #[command(flatten)]
+    dirs: DirSelection,
+    /// Only the folders older than this many days, like 30 or 30d
+    #[arg(long, value_name = "DAYS", value_parser = parse_days, default_value = "30")]
+    older_than: u64,
+    /// Every trash folder, whatever its age
+    #[arg(long, conflicts_with = "older_than")]
+    all: bool,
+  },
+  /// Download a remote file or folder anywhere
+  Restore {
+    #[command(flatten)]
+    dir: PathDir,
+    /// Remote file or folder
+    remote_path: String,
+    /// Local destination
+    destination: String,
+    /// What to do with files that already exist at the destination
+    #[arg(long, default_value = "skip", value_parser = ["skip", "overwrite", "rename"])]
+    overwrite: String,
+    /// Only restore files matching this pattern, can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+    /// Leave out files matching this pattern, can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Snapshot of a backup dir to restore from, `latest` for the newest one
+    #[arg(long, value_name = "NAME")]
+    snapshot: Option<String>,
+  },
+  /// Compare local and remote files without changing anything, exits with 1 on differences
+  Verify {
+    #[command(flatten)]
+    dirs: DirSelection,
+    /// Also compare file hashes
+    #[arg(long)]
+    checksum: bool,
+  },
+  /// Show the pending uploads, downloads, conflicts and deletions
+  Status {
+    #[command(flatten)]
+    dirs: DirSelection,
+  },
+  /// List a remote folder
+  Ls {
+    #[command(flatten)]
+    dir: PathDir,
+    #[arg(default_value = "")]
+    path: String,
+    /// Also show the type, size and date of every entry
+    #[arg(short, long)]
+    long: bool,
+  },
+  /// Show the remote folder tree
+  Tree {
+    #[command(flatten)]
+    dir: PathDir,
+    #[arg(default_value = "")]
+    path: String,
+    /// Levels to show, everything when not given
+    #[arg(long)]
+    depth: Option<u32>,
+  },
+  /// Show the properties of a remote file or folder
+  Stat {
+    #[command(flatten)]
+    dir: PathDir,
+    path: String,
+  },
+  /// Show the total size of the remote folders
+  Du {
+    #[command(flatten)]
+    dir: PathDir,
+    #[arg(default_value = "")]
+    path: String,
+    /// Levels of folders to show, every one when not given
+    #[arg(long)]
+    depth: Option<u32>,
+  },
+  /// Upload a local file
+  Put {
+    #[command(flatten)]
+    dir: PathDir,
+    local: String,
+    remote: String,
+  },
+  /// Download a remote file
+  Get {
+    #[command(flatten)]
+    dir: PathDir,
+    remote: String,
+    local: String,
+  },
+  /// Create a remote folder
+  Mkdir {
+    #[command(flatten)]
+    dir: PathDir,
+    path: String,
+    /// Also create the missing parents
+    #[arg(short, long)]
+    parents: bool,
+  },
+  /// Delete a remote file or folder
+  Rm {
+    #[command(flatten)]
+    dir: PathDir,
+    path: String,
+    /// Needed to delete folders with their content
+    #[arg(short, long)]
+    recursive: bool,
+  },
+  /// Move or rename a remote file or folder
+  Mv {
+    #[command(flatten)]
+    dir: PathDir,
+    source: String,
+    destination: String,
+  },
+  /// Copy a remote file or folder
+  Cp {
+    #[command(flatten)]
+    dir: PathDir,
+    source: String,
+    destination: String,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+  /// Report every problem of the config file, exits with 1 on errors
+  Check,
+  /// Write the config file in another format, comments are not kept
+  Convert {
+    /// New config file, `-` prints it
+    destination: String,
+    /// Format to write, taken from the destination extension when not given
+    #[arg(long, value_name = "FORMAT", value_parser = config_file::FORMATS)]
+    to: Option<String>,
+    /// Replace the destination when it exists
+    #[arg(short, long)]
+    force: bool,
+  },
+}
+
+impl Command {
+  /// Dir selection of the commands that work on remote paths.
+  pub fn path_dir(&self) -> Option<&PathDir> {
+    match self {
+      Command::Restore { dir, .. } | Command::Ls { dir, .. } | Command::Tree { dir, .. } | Command::Stat { dir, .. }
+        | Command::Du { dir, .. } | Command::Put { dir, .. } | Command::Get { dir, .. } | Command::Mkdir { dir, .. }
+        | Command::Rm { dir, .. } | Command::Mv { dir, .. } | Command::Cp { dir, .. } => Some(dir),
+      _ => None,
+    }
+  }
+}
+
+/// Parses a number of days, with an optional `d` suffix.
+fn parse_days(value: &str) -> Result<u64, String> {
+  value.trim_end_matches('d').parse::<u64>().map_err(|_| format!("{} is not a number of days", value))
+}
@@ -0,0 +1,526 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::collections::HashMap;
+use std::fmt::Display;
+use serde::Serialize;
+use serde_json::json;
+use crate::core::api_conn::ApiError;
+use crate::core::cli::{Cli, Command, ConfigAction, DirSelection, PathDir};
+use crate::core::config_file::{ConfigError, Problem};
+use crate::core::engine::{self, SyncContext};
+use crate::core::objects::{CompareMode, Configfile, Dirsync, Server, SyncMode};
+use crate::core::remote::Remote;
+use crate::core::init::{self, InitOptions};
+use crate::core::restore::RestoreOptions;
+use crate::core::{browse, config_file, file_ops, output, restore, status, trash, utils, verify, versions};
+
+fn fail(err: impl Display) -> i32 {
+  output::error(err);
+  1
+}
+
+/// Whether `dir` is the one `--dir` names, by index, name or name pattern.
+fn dir_matches(index: usize, dir: &Dirsync, selector: &str) -> bool {
+  match selector.parse::<usize>() {
+    Ok(selected) => selected == index,
+    Err(_) => !dir.name.is_empty() && utils::matches_pattern(selector, &dir.name),
+  }
+}
+
+/// Dirs picked with `--dir` and `--tag`, every dir without them. Every selector has to pick one at least.
+fn selected_dirs<'a>(dirs: &'a [Dirsync], selection: &DirSelection) -> Result<Vec<&'a Dirsync>, String> {
+  if selection.dir.is_empty() && selection.tag.is_empty() {
+    return Ok(dirs.iter().collect());
+  }
+  if let Some(selector) = selection.dir.iter().find(|selector| !dirs.iter().enumerate().any(|(index, dir)| dir_matches(index, dir, selector))) {
+    return Err(format!("no dir matches {}", selector));
+  }
+  if let Some(tag) = selection.tag.iter().find(|tag| !dirs.iter().any(|dir| dir.tags.contains(tag))) {
+    return Err(format!("no dir is tagged {}", tag));
+  }
+
+  Ok(dirs.iter().enumerate()
+    .filter(|(index, dir)| selection.dir.iter().any(|selector| dir_matches(*index, dir, selector)) || dir.tags.iter().any(|tag| selection.tag.contains(tag)))
+    .map(|(_, dir)| dir)
+    .collect())
+}
+
+/// The one dir `selector` picks, by index, name or a name pattern matching a single dir.
+fn find_dir<'a>(dirs: &'a [Dirsync], selector: &str) -> Result<&'a Dirsync, String> {
+  let matching: Vec<&Dirsync> = dirs.iter().enumerate()
+    .filter(|(index, dir)| dir_matches(*index, dir, selector))
+    .map(|(_, dir)| dir)
+    .collect();
+  match matching.as_slice() {
+    [dir] => Ok(dir),
+    [] => Err(format!("no dir matches {}", selector)),
+    _ => Err(format!("{} matches {} dirs, only one can be used", selector, matching.len())),
+  }
+}
+
+/// Dir the remote paths of a command are relative to. Without `--dir` they are plain server
+/// paths, handled with `adhoc_dir`.
+fn path_dir<'a>(dirs: &'a [Dirsync], selection: &PathDir, adhoc_dir: &'a Dirsync) -> Result<&'a Dirsync, String> {
+  match &selection.dir {
+    Some(selector) => find_dir(dirs, selector),
+    None => Ok(adhoc_dir),
+  }
+}
+
+fn context<'a>(cli: &Cli, dir: &'a Dirsync, remote: &'a Remote) -> Result<SyncContext<'a>, ApiError> {
+  let mut ctx = SyncContext::new(dir, remote)?;
+  ctx.dry_run = cli.dry_run;
+  Ok(ctx)
+}
+
+async fn path_context<'a>(cli: &Cli, dirs: &'a [Dirsync], selection: &PathDir, adhoc_dir: &'a Dirsync, connections: &'a mut Connections<'_>) -> Result<SyncContext<'a>, String> {
+  let dir = path_dir(dirs, selection, adhoc_dir)?;
+  let remote = connections.remote(&dir.server).await?;
+  context(cli, dir, remote).map_err(|err| err.to_string())
+}
+
+/// The value of `result`, or `{"error": ...}`.
+fn json_result<T: Serialize, E: Display>(result: &Result<T, E>) -> serde_json::Value {
+  match result {
+    Ok(value) => serde_json::to_value(value).unwrap_or_default(),
+    Err(err) => json!({ "error": err.to_string() }),
+  }
+}
+
+/// Prints `result` as JSON or with `display`, returns the exit status.
+fn report<T: Serialize>(cli: &Cli, result: Result<T, ApiError>, display: impl FnOnce(&T)) -> i32 {
+  match result {
+    Ok(value) if cli.json() => {
+      output::print_json(&value);
+      0
+    },
+    Ok(value) => {
+      display(&value);
+      0
+    },
+    Err(err) => fail(err),
+  }
+}
+
+fn report_message<E: Display>(result: Result<String, E>) -> i32 {
+  match result {
+    Ok(message) => {
+      println!("{}", message);
+      0
+    },
+    Err(err) => fail(err),
+  }
+}
+
+pub async fn connect(name: &str, server: &Server) -> Result<Remote, String> {
+  let remote = Remote::new(server).map_err(|err| err.to_string())?;
+  let label = if name.is_empty() { server.base_url.as_str() } else { name };
+
+  match &remote {
+    Remote::Api(api_client) => {
+      crate::detail!("Authenticating with {}...", label);
+      let user = api_client.auth().await.map_err(|err| format!("{} ({})", err, label))?;
+      crate::detail!("Authenticated as {}", user.username);
+      crate::detail!("User ID: {}", user.user_id);
+      crate::detail!("Session ID: {}", user.session_id);
+
+      let scopes = api_client.get_api_scopes().await.map_err(|err| format!("{} ({})", err, label))?;
+      if !utils::is_authorized(&scopes) {
+        return Err(format!("api key has not enough permissions ({})", label));
+      }
+    },
+    Remote::Local(local_client) => {
+      crate::detail!("Using local backend at {}", local_client.root().display());
+    },
+  }
+  Ok(remote)
+}
+
+/// Servers connected so far. Each one is connected and authenticated once, when the first dir
+/// using it needs it, and a failure is kept so it isn't retried for every dir.
+struct Connections<'a> {
+  config: &'a Configfile,
+  remotes: HashMap<String, Result<Remote, String>>,
+}
+
+impl<'a> Connections<'a> {
+  fn new(config: &'a Configfile) -> Self {
+    Self { config, remotes: HashMap::new() }
+  }
+
+  async fn remote(&mut self, name: &str) -> Result<&Remote, String> {
+    if !self.remotes.contains_key(name) {
+      let result = match config_file::server(self.config, name) {
+        Some(server) => connect(name, server).await,
+        None if name.is_empty() => Err(String::from("the config file has no top level server, use --server")),
+        None => Err(format!("server {} is not in the config file", name)),
+      };
+      self.remotes.insert(name.to_string(), result);
+    }
+    self.remotes[name].as_ref().map_err(|err| err.clone())
+  }
+}
+
+fn list(cli: &Cli, dirs: &[Dirsync]) -> i32 {
+  if !cli.json() {
+    utils::display_dirs_list(dirs);
+    return 0;
+  }
+  let list: Vec<serde_json::Value> = dirs.iter().enumerate()
+    .map(|(index, dir)| json!({
+      "index": index,
+      "name": dir.name,
+      "tags": dir.tags,
+      "server": dir.server,
+      "sync_mode": dir.sync_mode,
+      "local_path": dir.local_path,
+      "remote_path": dir.remote_path,
+    }))
+    .collect();
+  output::print_json(&list);
+  0
+}
+
+fn show_versions(cli: &Cli, dirs: &[Dirsync], selection: &DirSelection, filter: &str) -> i32 {
+  let selected = match selected_dirs(dirs, selection) {
+    Ok(selected) => selected,
+    Err(err) => return fail(err),
+  };
+  if !cli.json() {
+    for dir in selected {
+      versions::display_versions(dir, filter);
+    }
+    return 0;
+  }
+  let list: Vec<serde_json::Value> = selected.iter()
+    .map(|dir| json!({ "name": dir.name, "local_path": dir.local_path, "versions": json_result(&versions::list_versions(dir, filter)) }))
+    .collect();
+  output::print_json(&list);
+  0
+}
+
+fn restore_version(cli: &Cli, dirs: &[Dirsync], selector: &str, path: &str, stamp: &str) -> i32 {
+  let dir = match find_dir(dirs, selector) {
+    Ok(dir) => dir,
+    Err(err) => return fail(err),
+  };
+  let result = match cli.dry_run {
+    true => versions::find_version(dir, path, stamp).map(|version| format!("would restore {} ({})", path, version.stamp)),
+    false => versions::restore(dir, path, stamp).map(|restored| format!("restored {} ({})", path, restored)),
+  };
+  report_message(result)
+}
+
+async fn sync(cli: &Cli, dirs: &[Dirsync], selection: &DirSelection, connections: &mut Connections<'_>) -> i32 {
+  let selected = match selected_dirs(dirs, selection) {
+    Ok(selected) => selected,
+    Err(err) => return fail(err),
+  };
+  crate::detail!("Syncing files...");
+
+  let mut status = 0;
+  for dir in selected {
+    let remote = match connections.remote(&dir.server).await {
+      Ok(remote) => remote,
+      Err(err) => {
+        status = fail(err);
+        continue;
+      },
+    };
+    match engine::sync_files(dir, remote, cli.dry_run).await {
+      Ok(message) => println!("{}", message),
+      Err(err) => status = fail(err),
+    }
+  }
+  status
+}
+
+async fn purge_trash(cli: &Cli, dirs: &[Dirsync], selection: &DirSelection, older_than: Option<u64>, connections: &mut Connections<'_>) -> i32 {
+  let selected = match selected_dirs(dirs, selection) {
+    Ok(selected) => selected,
+    Err(err) => return fail(err),
+  };
+  let mut status = 0;
+  for dir in selected {
+    let remote = match connections.remote(&dir.server).await {
+      Ok(remote) => remote,
+      Err(err) => {
+        status = fail(err);
+        continue;
+      },
+    };
+    match trash::purge(dir, remote, older_than, cli.dry_run).await {
+      Ok(purged) if cli.dry_run => println!("{} trash folders would be purged in {}", purged, dir.remote_path),
+      Ok(purged) => println!("{} trash folders purged in {}", purged, dir.remote_path),
+      Err(err) => status = fail(err),
+    }
+  }
+  status
+}
+
+/// Exits with 1 when anything differs, so it can run as a scheduled audit.
+async fn verify(cli: &Cli, dirs: &[Dirsync], selection: &DirSelection, checksum: bool, connections: &mut Connections<'_>) -> i32 {
+  let selected = match selected_dirs(dirs, selection) {
+    Ok(selected) => selected,
+    Err(err) => return fail(err),
+  };
+
+  let mut differs = false;
+  let mut reports: Vec<serde_json::Value> = Vec::new();
+  for dir in selected {
+    if !cli.json() {
+      println!("verifying {} against {}", dir.local_path, dir.remote_path);
+    }
+    let result = match connections.remote(&dir.server).await {
+      Ok(remote) => match context(cli, dir, remote) {
+        Ok(mut ctx) => verify::verify(&mut ctx, checksum || dir.compare_mode == CompareMode::Checksum).await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+      },
+      Err(err) => Err(err),
+    };
+    differs = differs || result.as_ref().is_ok_and(|report| report.differences() > 0) || result.is_err();
+
+    if cli.json() {
+      reports.push(json!({ "name": dir.name, "local_path": dir.local_path, "remote_path": dir.remote_path, "report": json_result(&result) }));
+      continue;
+    }
+    match result {
+      Ok(report) => println!("{}", report.summary()),
+      Err(err) => {
+        fail(err);
+      },
+    }
+  }
+
+  if cli.json() {
+    output::print_json(&reports);
+  }
+  differs as i32
+}
+
+async fn show_status(cli: &Cli, dirs: &[Dirsync], selection: &DirSelection, connections: &mut Connections<'_>) -> i32 {
+  let selected = match selected_dirs(dirs, selection) {
+    Ok(selected) => selected,
+    Err(err) => return fail(err),
+  };
+
+  let mut exit_status = 0;
+  let mut list: Vec<serde_json::Value> = Vec::new();
+  for dir in selected {
+    let remote = match connections.remote(&dir.server).await {
+      Ok(remote) => remote,
+      Err(err) => {
+        exit_status = fail(err);
+        continue;
+      },
+    };
+    let mut ctx = match context(cli, dir, remote) {
+      Ok(ctx) => ctx,
+      Err(err) => {
+        exit_status = fail(err);
+        continue;
+      },
+    };
+    if !cli.json() {
+      if let Err(err) = status::display_status(&mut ctx).await {
+        exit_status = fail(err);
+      }
+      continue;
+    }
+    let pending = status::dir_status(&mut ctx).await;
+    if pending.is_err() {
+      exit_status = 1;
+    }
+    list.push(json!({
+      "name": dir.name,
+      "local_path": dir.local_path,
+      "remote_path": dir.remote_path,
+      "sync_mode": dir.sync_mode,
+      "last_sync": ctx.state.last_sync,
+      "pending": json_result(&pending),
+    }));
+  }
+
+  if cli.json() {
+    output::print_json(&list);
+  }
+  exit_status
+}
+
+fn convert_config(cli: &Cli, config_path: &str, destination: &str, to: &Option<String>, force: bool) -> i32 {
+  let to = match to {
+    Some(format) => format.clone(),
+    None => config_file::format_of(destination).to_string(),
+  };
+  let converted = match config_file::convert(config_path, &cli.config_format(config_path), &to) {
+    Ok(converted) => converted,
+    Err(err) => return fail(format!("{}: {}", config_path, err)),
+  };
+
+  if destination == "-" {
+    print!("{}", converted);
+    return 0;
+  }
+  if !force && config_file::exists_file(destination).unwrap_or(true) {
+    return fail(format!("{} already exists, use --force to replace it", destination));
+  }
+  if cli.dry_run {
+    println!("would write {} as {}", destination, to);
+    return 0;
+  }
+  match std::fs::write(destination, converted) {
+    Ok(_) => {
+      println!("{} written as {}", destination, to);
+      0
+    },
+    Err(err) => fail(format!("{} ({})", err, destination)),
+  }
+}
+
+/// Reports every problem of the config file, exits with 1 when one of them is an error.
+fn check_config(cli: &Cli, config_path: &str) -> i32 {
+  let problems = match config_file::read_config(config_path, &cli.config_format(config_path)) {
+    Ok((config, mut problems)) => {
+      config_file::check_rest(&config, &mut problems);
+      problems
+    },
+    Err(ConfigError::Invalid(problems)) => problems,
+    Err(err) => vec![Problem { field: config_path.to_string(), message: err.to_string(), warning: false }],
+  };
+  let errors = problems.iter().filter(|problem| !problem.warning).count();
+
+  if cli.json() {
+    output::print_json(&json!({ "file": config_path, "valid": errors == 0, "problems": problems }));
+  } else {
+    for problem in problems.iter() {
+      println!("{} {}", if problem.warning { "warning" } else { "error  " }, problem);
+    }
+    println!("{}: {} errors, {} warnings", config_path, errors, problems.len() - errors);
+  }
+  if errors > 0 { 1 } else { 0 }
+}
+
+/// Runs the command of `cli` and returns the exit status.
+pub async fn run(cli: &Cli) -> i32 {
+  let config_path = cli.config_path();
+  if let Command::Init { url, api_key, api_key_env, add_dir, no_verify, non_interactive, force } = &cli.command {
+    if !force && config_file::exists_file(&config_path).unwrap_or(true) {
+      return fail(format!("{} already exists, use --force to replace it", config_path));
+    }
+    let options = InitOptions {
+      url: url.clone(),
+      api_key: api_key.clone(),
+      api_key_env: api_key_env.clone(),
+      dirs: add_dir.chunks(3).map(|dir| (dir[0].clone(), dir[1].clone(), dir[2].clone())).collect(),
+      verify: !no_verify,
+      interactive: !non_interactive,
+    };
+    return report_message(init::init(&config_path, &cli.config_format(&config_path), &options, cli.dry_run).await);
+  }
+  match config_file::exists_file(&config_path) {
+    Ok(true) => {},
+    Ok(false) => return fail(format!("config file {} does not exist", config_path)),
+    Err(err) => return fail(format!("verifying the existence of {}: {}", config_path, err)),
+  }
+  match &cli.command {
+    Command::Config { action: ConfigAction::Convert { destination, to, force } } => return convert_config(cli, &config_path, destination, to, *force),
+    Command::Config { action: ConfigAction::Check } => return check_config(cli, &config_path),
+    _ => {},
+  }
+  let config = match config_file::load_config(&config_path, &cli.config_format(&config_path)) {
+    Ok(config) => config,
+    Err(err) => return fail(format!("{}: {}", config_path, err)),
+  };
+  for warning in config_file::check(&config).iter().filter(|problem| problem.warning) {
+    output::warning(warning);
+  }
+  let dirs = &config.dirs;
+
+  // these only look at local files, the server isn't contacted
+  match &cli.command {
+    Command::List => return list(cli, dirs),
+    Command::Versions { dirs: selection, path } => return show_versions(cli, dirs, selection, path.as_deref().unwrap_or("")),
+    Command::RestoreVersion { dir, path, version } => return restore_version(cli, dirs, dir, path, version.as_deref().unwrap_or("")),
+    _ => {},
+  }
+
+  let mut connections = Connections::new(&config);
+  let connections = &mut connections;
+  // without --dir the remote paths are plain server paths, handled with a dir of their own
+  let server = cli.command.path_dir().and_then(|selection| selection.server.clone()).unwrap_or_default();
+  let adhoc_dir = match &cli.command {
+    Command::Restore { destination, .. } => Dirsync { server, local_path: destination.clone(), sync_mode: SyncMode::Get, ..Default::default() },
+    // the path commands only use the connection and the path rules of the dir, never its mode
+    _ => Dirsync { server, sync_mode: SyncMode::Get, ..Default::default() },
+  };
+
+  match &cli.command {
+    Command::Sync { dirs: selection } => sync(cli, dirs, selection, connections).await,
+    Command::PurgeTrash { dirs: selection, older_than, all } => {
+      let older_than = if *all { None } else { Some(*older_than) };
+      purge_trash(cli, dirs, selection, older_than, connections).await
+    },
+    Command::Verify { dirs: selection, checksum } => verify(cli, dirs, selection, *checksum, connections).await,
+    Command::Status { dirs: selection } => show_status(cli, dirs, selection, connections).await,
+    Command::Restore { dir, remote_path, destination, overwrite, include, exclude, snapshot } => {
+      let options = RestoreOptions {
+        overwrite: overwrite.clone(),
+        include: include.clone(),
+        exclude: exclude.clone(),
+        snapshot: snapshot.clone().unwrap_or_default(),
+      };
+      let mut ctx = match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+        Ok(ctx) => ctx,
+        Err(err) => return fail(err),
+      };
+      if !options.snapshot.is_empty() && ctx.dir.sync_mode != SyncMode::Backup {
+        return fail("--snapshot needs a dir with the backup sync mode");
+      }
+      report_message(restore::restore(&mut ctx, remote_path, destination, &options).await)
+    },
+    Command::Ls { dir, path, long } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report(cli, browse::ls(&mut ctx, path).await, |list| browse::display_ls(list, *long)),
+      Err(err) => fail(err),
+    },
+    Command::Tree { dir, path, depth } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report(cli, browse::tree(&mut ctx, path, *depth).await, browse::display_tree),
+      Err(err) => fail(err),
+    },
+    Command::Stat { dir, path } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report(cli, browse::stat(&mut ctx, path).await, browse::display_stat),
+      Err(err) => fail(err),
+    },
+    Command::Du { dir, path, depth } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report(cli, browse::du(&mut ctx, path, *depth).await, |usage| browse::display_du(usage)),
+      Err(err) => fail(err),
+    },
+    Command::Put { dir, local, remote: remote_path } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::put(&mut ctx, local, remote_path).await),
+      Err(err) => fail(err),
+    },
+    Command::Get { dir, remote: remote_path, local } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::get(&mut ctx, remote_path, local).await),
+      Err(err) => fail(err),
+    },
+    Command::Mkdir { dir, path, parents } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::mkdir(&mut ctx, path, *parents).await),
+      Err(err) => fail(err),
+    },
+    Command::Rm { dir, path, recursive } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::rm(&mut ctx, path, *recursive).await),
+      Err(err) => fail(err),
+    },
+    Command::Mv { dir, source, destination } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::mv(&mut ctx, source, destination).await),
+      Err(err) => fail(err),
+    },
+    Command::Cp { dir, source, destination } => match path_context(cli, dirs, dir, &adhoc_dir, connections).await {
+      Ok(mut ctx) => report_message(file_ops::cp(&mut ctx, source, destination).await),
+      Err(err) => fail(err),
+    },
+    Command::List | Command::Versions { .. } | Command::RestoreVersion { .. } | Command::Config { .. } | Command::Init { .. } => 0,
+  }
+}
@@ -0,0 +1,195 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use crate::core::{compression, crypto};
+use crate::core::objects::{CompareMode, Dirsync, File, FileStat, HashAlgorithm, SyncMode};
+use crate::core::sync_state::FileRecord;
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+  Same,
+  Upload,
+  Download,
+  Conflict,
+}
+
+pub fn receives(sync_mode: SyncMode) -> bool {
+  matches!(sync_mode, SyncMode::Get | SyncMode::Bidirectional)
+}
+
+pub fn sends(sync_mode: SyncMode) -> bool {
+  matches!(sync_mode, SyncMode::Send | SyncMode::Bidirectional)
+}
+
+fn remote_size(remote: &File, record: Option<&FileRecord>) -> Option<u64> {
+  remote.size.or(record.and_then(|r| r.remote_size))
+}
+
+fn is_compressed(dir: &Dirsync, name: &str) -> bool {
+  dir.compression.as_ref().is_some_and(|compression| compression::applies(compression, name))
+}
+
+/// Size the remote copy of `local` should have once uploaded, unknown for files that may be compressed.
+pub fn expected_remote_size(dir: &Dirsync, local: &FileStat, name: &str) -> Option<u64> {
+  if is_compressed(dir, name) {
+    return None;
+  }
+  let stored_size = match dir.compression {
+    Some(_) => local.size + compression::MARKER_SIZE,
+    None => local.size,
+  };
+  match dir.encryption {
+    Some(_) => Some(crypto::encrypted_size(stored_size)),
+    None => Some(stored_size),
+  }
+}
+
+fn stat_changed(local: &FileStat, record: Option<&FileRecord>) -> bool {
+  match record {
+    Some(r) => r.size != local.size || r.mtime != local.mtime || r.mtime_nsec != local.mtime_nsec,
+    None => true,
+  }
+}
+
+fn remote_changed(remote: &File, record: Option<&FileRecord>) -> bool {
+  match (record, remote.size) {
+    (Some(r), Some(size)) => r.remote_size != Some(size),
+    (Some(_), None) => false,
+    (None, _) => true,
+  }
+}
+
+/// Tells whether both sides hold different content according to `compare_mode`.
+/// `local_hash` is only needed (and only computed by the caller) for `checksum`.
+pub fn differs(dir: &Dirsync, local: &FileStat, local_hash: Option<&str>, remote: &File, record: Option<&FileRecord>) -> bool {
+  let size_differs = match expected_remote_size(dir, local, &remote.name) {
+    Some(expected) => remote_size(remote, record).is_some_and(|size| size != expected),
+    // compressed sizes can't be predicted, so the original size is compared with the last transfer
+    None => record.is_some_and(|r| r.size != local.size || remote_changed(remote, Some(r))),
+  };
+
+  match dir.compare_mode {
+    CompareMode::Name => false,
+    CompareMode::Size => size_differs,
+    CompareMode::SizeMtime => size_differs || (record.is_some() && stat_changed(local, record)),
+    CompareMode::Checksum => {
+      // the server hashes what it stores, which only matches the local hash for plain files
+      let is_plain = dir.encryption.is_none() && dir.compression.is_none();
+      let remote_hash = remote.sha256.as_deref().filter(|_| dir.hash_algorithm == HashAlgorithm::Sha256 && is_plain);
+      let known_hash = remote_hash.or(record.and_then(|r| r.hash.as_deref()));
+      match (known_hash, local_hash) {
+        (Some(known), Some(local)) => !known.eq_ignore_ascii_case(local),
+        _ => size_differs,
+      }
+    },
+  }
+}
+
+pub fn decide(dir: &Dirsync, local: &FileStat, local_hash: Option<&str>, remote: &File, record: Option<&FileRecord>) -> Decision {
+  if !differs(dir, local, local_hash, remote, record) {
+    return Decision::Same;
+  }
+
+  match dir.sync_mode {
+    SyncMode::Send => Decision::Upload,
+    SyncMode::Get => Decision::Download,
+    SyncMode::Bidirectional | SyncMode::Backup => match (stat_changed(local, record), remote_changed(remote, record)) {
+      (true, false) => Decision::Upload,
+      (false, true) => Decision::Download,
+      _ => Decision::Conflict,
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::objects::{Compression, EntryType};
+
+  fn dir(sync_mode: SyncMode, compare_mode: CompareMode) -> Dirsync {
+    Dirsync { sync_mode, compare_mode, ..Default::default() }
+  }
+
+  fn local(size: u64, mtime: i64) -> FileStat {
+    FileStat { is_dir: false, size, mtime, mtime_nsec: 0, dev: 0, ino: 0 }
+  }
+
+  fn remote(size: Option<u64>, sha256: Option<&str>) -> File {
+    File {
+      name: String::from("notes.txt"),
+      r#type: EntryType::File,
+      size,
+      sha256: sha256.map(str::to_string),
+      extension: None,
+      mime_type: None,
+      modified: None,
+    }
+  }
+
+  fn record(size: u64, mtime: i64, hash: Option<&str>, remote_size: Option<u64>) -> FileRecord {
+    FileRecord { size, mtime, mtime_nsec: 0, hash: hash.map(str::to_string), remote_size }
+  }
+
+  #[test]
+  fn name_mode_only_transfers_missing_files() {
+    let dir = dir(SyncMode::Send, CompareMode::Name);
+    assert_eq!(decide(&dir, &local(10, 1), None, &remote(Some(99), None), None), Decision::Same);
+  }
+
+  #[test]
+  fn size_mode_ignores_mtime() {
+    let dir = dir(SyncMode::Send, CompareMode::Size);
+    let last = record(10, 1, None, Some(10));
+    assert_eq!(decide(&dir, &local(10, 2), None, &remote(Some(10), None), Some(&last)), Decision::Same);
+    assert_eq!(decide(&dir, &local(11, 1), None, &remote(Some(10), None), Some(&last)), Decision::Upload);
+  }
+
+  #[test]
+  fn size_mtime_mode_sends_changes_that_keep_the_size() {
+    let dir = dir(SyncMode::Send, CompareMode::SizeMtime);
+    let last = record(10, 1, None, Some(10));
+    assert_eq!(decide(&dir, &local(10, 1), None, &remote(Some(10), None), Some(&last)), Decision::Same);
+    assert_eq!(decide(&dir, &local(10, 2), None, &remote(Some(10), None), Some(&last)), Decision::Upload);
+    // without a record the mtime can't be compared with anything
+    assert_eq!(decide(&dir, &local(10, 2), None, &remote(Some(10), None), None), Decision::Same);
+  }
+
+  #[test]
+  fn checksum_mode_compares_hashes_of_files_with_the_same_size() {
+    let dir = dir(SyncMode::Get, CompareMode::Checksum);
+    assert_eq!(decide(&dir, &local(10, 1), Some("abc"), &remote(Some(10), Some("ABC")), None), Decision::Same);
+    assert_eq!(decide(&dir, &local(10, 1), Some("abc"), &remote(Some(10), Some("def")), None), Decision::Download);
+    // no hash to compare with, the sizes decide
+    assert_eq!(decide(&dir, &local(10, 1), Some("abc"), &remote(Some(10), None), None), Decision::Same);
+    assert_eq!(decide(&dir, &local(10, 1), Some("abc"), &remote(Some(12), None), None), Decision::Download);
+  }
+
+  #[test]
+  fn checksum_mode_with_blake3_uses_the_recorded_hash() {
+    let mut dir = dir(SyncMode::Send, CompareMode::Checksum);
+    dir.hash_algorithm = HashAlgorithm::Blake3;
+    let last = record(10, 1, Some("abc"), Some(10));
+    assert_eq!(decide(&dir, &local(10, 2), Some("abc"), &remote(Some(10), Some("def")), Some(&last)), Decision::Same);
+    assert_eq!(decide(&dir, &local(10, 2), Some("bcd"), &remote(Some(10), Some("bcd")), Some(&last)), Decision::Upload);
+  }
+
+  #[test]
+  fn compressed_files_compare_the_size_of_the_last_transfer() {
+    let mut dir = dir(SyncMode::Send, CompareMode::Size);
+    dir.compression = Some(Compression { level: 3, skip_extensions: Vec::new() });
+    let last = record(10, 1, None, Some(7));
+    assert_eq!(decide(&dir, &local(10, 1), None, &remote(Some(7), None), Some(&last)), Decision::Same);
+    assert_eq!(decide(&dir, &local(11, 1), None, &remote(Some(7), None), Some(&last)), Decision::Upload);
+  }
+
+  #[test]
+  fn bidirectional_mode_follows_the_side_that_changed() {
+    let dir = dir(SyncMode::Bidirectional, CompareMode::SizeMtime);
+    let last = record(10, 1, None, Some(10));
+    assert_eq!(decide(&dir, &local(12, 2), None, &remote(Some(10), None), Some(&last)), Decision::Upload);
+    assert_eq!(decide(&dir, &local(10, 1), None, &remote(Some(12), None), Some(&last)), Decision::Download);
+    assert_eq!(decide(&dir, &local(11, 2), None, &remote(Some(12), None), Some(&last)), Decision::Conflict);
+  }
+}
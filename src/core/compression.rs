@@ -0,0 +1,62 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::core::{file_conn, sync_state};
+use crate::core::objects::Compression;
+
+// Every file uploaded to a dir with compression starts with one of these markers,
+// so downloads know when to decompress.
+const MARKER: &[u8; 5] = b"KCSZ\x01";
+const RAW_MARKER: &[u8; 5] = b"KCSZ\x00";
+
+/// Bytes the marker adds to the remote copy.
+pub const MARKER_SIZE: u64 = MARKER.len() as u64;
+
+/// Tells whether `name` should be compressed, media and archives are skipped.
+pub fn applies(compression: &Compression, name: &str) -> bool {
+  let extension = Path::new(name)
+    .extension()
+    .map(|extension| extension.to_string_lossy().to_lowercase())
+    .unwrap_or_default();
+
+  !compression.skip_extensions.iter().any(|skip| skip.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+}
+
+/// Compresses `source` while it is read, the marker comes first.
+fn compress_reader<R: Read + Send + 'static>(compression: &Compression, source: R) -> io::Result<Box<dyn Read + Send>> {
+  let encoder = zstd::stream::read::Encoder::new(source, compression.level)?;
+  Ok(Box::new(io::Cursor::new(MARKER).chain(encoder)))
+}
+
+/// `source` as it is, behind the marker of files sent without compression.
+pub fn raw_reader<R: Read + Send + 'static>(source: R) -> Box<dyn Read + Send> {
+  Box::new(io::Cursor::new(RAW_MARKER).chain(source))
+}
+
+/// Compressed copy of `path`, marker included, and its size. The copy is written to a scratch file
+/// of the state dir that is unlinked right away, the handle keeps it until it is dropped.
+pub fn compressed_copy(compression: &Compression, path: &str) -> io::Result<(File, u64)> {
+  let temp_path = sync_state::temp_file("compress")?;
+  let mut copy = OpenOptions::new().read(true).write(true).create_new(true).open(&temp_path)?;
+  fs::remove_file(&temp_path)?;
+  let size = io::copy(&mut compress_reader(compression, File::open(path)?)?, &mut copy)?;
+  copy.seek(SeekFrom::Start(0))?;
+  Ok((copy, size))
+}
+
+/// Original content of `source`, read after its marker.
+pub fn decompress_reader<R: Read + Send + 'static>(mut source: R) -> io::Result<Box<dyn Read + Send>> {
+  let mut header = [0u8; MARKER.len()];
+  let filled = file_conn::read_chunk(&mut source, &mut header)?;
+  match &header[..filled] {
+    marker if marker == MARKER => Ok(Box::new(zstd::stream::read::Decoder::new(source)?)),
+    marker if marker == RAW_MARKER => Ok(Box::new(source)),
+    // files uploaded before compression was enabled have no marker
+    _ => Ok(Box::new(io::Cursor::new(header[..filled].to_vec()).chain(source))),
+  }
+}
@@ -3,49 +3,641 @@
  * Copyright(c) Kintaro Ponce
  * MIT Licensed
  */
+use std::env;
+use std::fmt;
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::string::String;
-use serde::{de::Error};
-use serde_json::{Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_path_to_error::Segment;
+use thiserror::Error;
 pub use crate::core::objects;
+use crate::core::{compare, credentials, utils};
 
-pub fn exists_file(file_name: &String) -> io::Result<bool> {
+pub const FORMATS: [&str; 3] = ["json", "toml", "yaml"];
+
+const CONFIG_NAMES: [&str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+
+/// Something wrong in a config file, `field` is where it is, like `dirs[3].local_path`.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+  pub field: String,
+  pub message: String,
+  /// Warnings are reported but don't stop the config from loading.
+  pub warning: bool,
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.field, self.message)
+  }
+}
+
+fn problem_list(problems: &[Problem]) -> String {
+  problems.iter().map(|problem| format!("\n  {}", problem)).collect()
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("error de lectura: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("error de JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("error de TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("error al escribir TOML: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+
+    #[error("error de YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("formato de configuración desconocido: {0}")]
+    UnknownFormat(String),
+
+    #[error("variable de entorno {variable} sin definir en {field}")]
+    UnsetVariable {
+        variable: String,
+        field: String,
+    },
+
+    #[error("valor inválido en {field}: {reason}")]
+    InvalidValue {
+        field: String,
+        reason: String,
+    },
+
+    #[error("archivo de configuración inválido:{}", problem_list(.0))]
+    Invalid(Vec<Problem>),
+}
+
+fn config_dir() -> PathBuf {
+  match env::var("XDG_CONFIG_HOME") {
+    Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("k-cloud-sync"),
+    _ => PathBuf::from(env::var("HOME").unwrap_or_else(|_| String::from("."))).join(".config").join("k-cloud-sync"),
+  }
+}
+
+/// The first `config.json`, `config.toml` or `config.yaml` found in the working dir, then in the
+/// user config dir. The json one in the user config dir when there is none.
+pub fn default_config_path() -> String {
+  let dirs = [PathBuf::new(), config_dir()];
+  for dir in dirs.iter() {
+    for name in CONFIG_NAMES {
+      let path = dir.join(name);
+      if path.exists() {
+        return path.display().to_string();
+      }
+    }
+  }
+  config_dir().join(CONFIG_NAMES[0]).display().to_string()
+}
+
+/// Format of a config file by its extension, json when it has none of the others.
+pub fn format_of(file_name: &str) -> &'static str {
+  match Path::new(file_name).extension().and_then(|extension| extension.to_str()) {
+    Some("toml") => "toml",
+    Some("yaml") | Some("yml") => "yaml",
+    _ => "json",
+  }
+}
+
+fn parse<T: DeserializeOwned>(raw: &str, format: &str) -> Result<T, ConfigError> {
+  match format {
+    "json" => Ok(serde_json::from_str(raw)?),
+    "toml" => Ok(toml::from_str(raw)?),
+    "yaml" => Ok(serde_yaml::from_str(raw)?),
+    _ => Err(ConfigError::UnknownFormat(format.to_string())),
+  }
+}
+
+pub fn exists_file(file_name: &str) -> io::Result<bool> {
   let exist = fs::exists(file_name)?;
   Ok(exist)
 }
 
-pub fn load_config(file_name: &String) -> Result<objects::Configfile> {
-  let raw_json: String = fs::read_to_string(file_name).expect("Unable to read file");
-  let info: objects::Configfile = serde_json::from_str(&raw_json)?;
-  let mut valid: bool = true;
-  if info.base_url.trim().is_empty() {
-    valid = false;
+/// Server named `name` in `servers`, the top level one for an empty name.
+pub fn server<'a>(config: &'a objects::Configfile, name: &str) -> Option<&'a objects::Server> {
+  match name.is_empty() {
+    true => Some(&config.server).filter(|server| !server.base_url.trim().is_empty()),
+    false => config.servers.get(name),
+  }
+}
+
+fn env_var(variable: &str, field: &str) -> Result<String, ConfigError> {
+  env::var(variable).map_err(|_| ConfigError::UnsetVariable { variable: variable.to_string(), field: field.to_string() })
+}
+
+/// What follows a leading `~`, `None` when `text` isn't relative to the home dir.
+fn home_relative(text: &str) -> Option<&str> {
+  match text == "~" || text.starts_with("~/") {
+    true => Some(&text[1..]),
+    false => None,
+  }
+}
+
+/// `text` with a leading `~` turned into the home dir, its variables are left as they are.
+fn expand_home(text: &str, field: &str) -> Result<String, ConfigError> {
+  match home_relative(text) {
+    Some(rest) => Ok(format!("{}{}", env_var("HOME", field)?, rest)),
+    None => Ok(text.to_string()),
+  }
+}
+
+/// Expands a leading `~`, `$VAR`, `${VAR}` and `${VAR:-default}` like a shell does, `$$` is a plain `$`.
+/// The default is used when the variable is unset or empty, any other unset variable is an error.
+fn expand_vars(text: &str, field: &str) -> Result<String, ConfigError> {
+  let mut expanded = String::new();
+  let mut rest = text;
+  if let Some(after) = home_relative(text) {
+    expanded.push_str(&env_var("HOME", field)?);
+    rest = after;
+  }
+
+  while let Some(position) = rest.find('$') {
+    expanded.push_str(&rest[..position]);
+    rest = &rest[position + 1..];
+
+    if let Some(after) = rest.strip_prefix('$') {
+      expanded.push('$');
+      rest = after;
+    } else if let Some(after) = rest.strip_prefix('{') {
+      let end = after.find('}').ok_or_else(|| ConfigError::InvalidValue { field: field.to_string(), reason: String::from("missing } after ${") })?;
+      let expression = &after[..end];
+      match expression.split_once(":-") {
+        Some((variable, default)) => match env::var(variable) {
+          Ok(value) if !value.is_empty() => expanded.push_str(&value),
+          _ => expanded.push_str(default),
+        },
+        None => expanded.push_str(&env_var(expression, field)?),
+      }
+      rest = &after[end + 1..];
+    } else {
+      let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+      match length {
+        0 => expanded.push('$'),
+        _ => expanded.push_str(&env_var(&rest[..length], field)?),
+      }
+      rest = &rest[length..];
+    }
+  }
+  expanded.push_str(rest);
+  Ok(expanded)
+}
+
+fn hostname() -> String {
+  fs::read_to_string("/proc/sys/kernel/hostname")
+    .map(|name| name.trim().to_string())
+    .or_else(|_| env::var("HOSTNAME"))
+    .unwrap_or_else(|_| String::from("localhost"))
+}
+
+/// `{hostname}` and `{date}` in remote paths, so several machines can share one config.
+fn expand_placeholders(text: &str) -> String {
+  if !text.contains('{') {
+    return text.to_string();
+  }
+  text.replace("{hostname}", &hostname())
+    .replace("{date}", &chrono::Local::now().format("%Y-%m-%d").to_string())
+}
+
+/// Api keys, key commands and passphrases are kept as they are written, a `$` in them is their own.
+fn is_literal(key: &str) -> bool {
+  key.starts_with("api_key") || key == "passphrase"
+}
+
+/// Expands every string of the parsed file, `field` is where it is, like `dirs[0].local_path`.
+fn expand_value(value: &mut serde_json::Value, field: &str) -> Result<(), ConfigError> {
+  let key = field.rsplit('.').next().unwrap_or(field);
+  match value {
+    serde_json::Value::String(text) if key == "api_key_file" => *text = expand_home(text, field)?,
+    serde_json::Value::String(_) if is_literal(key) => {},
+    serde_json::Value::String(text) => {
+      *text = expand_vars(text, field)?;
+      if field.ends_with("remote_path") {
+        *text = expand_placeholders(text);
+      }
+    },
+    serde_json::Value::Object(map) => {
+      for (key, item) in map.iter_mut() {
+        let item_field = if field.is_empty() { key.clone() } else { format!("{}.{}", field, key) };
+        expand_value(item, &item_field)?;
+      }
+    },
+    serde_json::Value::Array(list) => {
+      for (index, item) in list.iter_mut().enumerate() {
+        expand_value(item, &format!("{}[{}]", field, index))?;
+      }
+    },
+    _ => {},
+  }
+  Ok(())
+}
+
+/// Turns every `$` that `expand_value` would expand into `$$`, `key` is the key of `value`.
+pub fn escape_value(value: &mut serde_json::Value, key: &str) {
+  match value {
+    serde_json::Value::String(_) if is_literal(key) => {},
+    serde_json::Value::String(text) => *text = text.replace('$', "$$"),
+    serde_json::Value::Object(map) => map.iter_mut().for_each(|(key, item)| escape_value(item, key)),
+    serde_json::Value::Array(list) => list.iter_mut().for_each(|item| escape_value(item, key)),
+    _ => {},
+  }
+}
+
+/// Reports the value of `key` in `dir` when it is missing or not one of `T`, and puts the default
+/// of `T` in its place so the rest of the config can still be read and checked.
+fn replace_invalid<T: DeserializeOwned + Serialize + Default>(dir: &mut serde_json::Map<String, serde_json::Value>, key: &str, field: &str, problems: &mut Vec<Problem>) {
+  let message = match dir.get(key).map(|value| serde_json::from_value::<T>(value.clone())) {
+    Some(Ok(_)) => return,
+    Some(Err(err)) => err.to_string(),
+    None => String::from("is missing"),
+  };
+  problems.push(Problem { field: format!("{}.{}", field, key), message, warning: false });
+  if let Ok(default) = serde_json::to_value(T::default()) {
+    dir.insert(key.to_string(), default);
+  }
+}
+
+/// Problems of the modes and hash algorithm of every dir, which would otherwise stop the config at the first one.
+fn check_modes(value: &mut serde_json::Value) -> Vec<Problem> {
+  let mut problems: Vec<Problem> = Vec::new();
+  let dirs = value.get_mut("dirs").and_then(serde_json::Value::as_array_mut);
+  for (i, dir) in dirs.into_iter().flatten().enumerate() {
+    if let Some(dir) = dir.as_object_mut() {
+      let field = format!("dirs[{}]", i);
+      replace_invalid::<objects::SyncMode>(dir, "sync_mode", &field, &mut problems);
+      if dir.contains_key("compare_mode") {
+        replace_invalid::<objects::CompareMode>(dir, "compare_mode", &field, &mut problems);
+      }
+      if dir.contains_key("hash_algorithm") {
+        replace_invalid::<objects::HashAlgorithm>(dir, "hash_algorithm", &field, &mut problems);
+      }
+    }
+  }
+  problems
+}
+
+/// Where `segments` point in `value`.
+fn value_at<'a>(value: &'a mut serde_json::Value, segments: &[Segment]) -> Option<&'a mut serde_json::Value> {
+  segments.iter().try_fold(value, |value, segment| match segment {
+    Segment::Seq { index } => value.get_mut(*index),
+    Segment::Map { key } => value.get_mut(key.as_str()),
+    _ => None,
+  })
+}
+
+/// Field of `segments` like `dirs[0].delta`, `root` for the config itself.
+fn field_of(segments: &[Segment], root: &str) -> String {
+  let mut field = String::new();
+  for segment in segments {
+    match segment {
+      Segment::Seq { index } => field.push_str(&format!("[{}]", index)),
+      Segment::Map { key } if field.is_empty() => field.push_str(key),
+      Segment::Map { key } => field.push_str(&format!(".{}", key)),
+      _ => {},
+    }
+  }
+  if field.is_empty() { root.to_string() } else { field }
+}
+
+/// Reads the expanded config. Every value of the wrong type is reported with its field and left
+/// out, and missing ones are read as empty strings, so `check` can still find the rest. `root`
+/// names the config in the problems that are not about one field.
+fn read_value(value: &mut serde_json::Value, root: &str, problems: &mut Vec<Problem>) -> Option<objects::Configfile> {
+  let mut placeholders: Vec<String> = Vec::new();
+  loop {
+    let err = match serde_path_to_error::deserialize::<_, objects::Configfile>(&*value) {
+      Ok(info) => return Some(info),
+      Err(err) => err,
+    };
+    let mut segments: Vec<Segment> = err.path().iter().cloned().collect();
+    let mut message = err.inner().to_string();
+    // the top level server is flattened, serde only tells where it is when it is read alone
+    if segments.is_empty() && let Err(err) = serde_path_to_error::deserialize::<_, objects::Server>(&*value)
+      && err.path().iter().next().is_some() {
+      segments = err.path().iter().cloned().collect();
+      message = err.inner().to_string();
+    }
+
+    let fixed = match message.strip_prefix("missing field `").and_then(|rest| rest.strip_suffix('`')) {
+      Some(key) => {
+        let mut field = field_of(&segments, "");
+        field = if field.is_empty() { key.to_string() } else { format!("{}.{}", field, key) };
+        // a value left out because of its type is already reported
+        if !problems.iter().any(|problem| problem.field == field) {
+          problems.push(Problem { field: field.clone(), message: String::from("is missing"), warning: false });
+        }
+        let fresh = !placeholders.contains(&field);
+        placeholders.push(field);
+        fresh && value_at(value, &segments).and_then(serde_json::Value::as_object_mut)
+          .map(|map| map.insert(key.to_string(), serde_json::Value::from("")))
+          .is_some()
+      },
+      // the placeholder doesn't fit either, the missing value was already reported
+      None if placeholders.contains(&field_of(&segments, root)) => false,
+      None => {
+        problems.push(Problem { field: field_of(&segments, root), message, warning: false });
+        match segments.split_last() {
+          Some((Segment::Map { key }, parent)) => value_at(value, parent).and_then(serde_json::Value::as_object_mut)
+            .and_then(|map| map.remove(key))
+            .is_some(),
+          _ => false,
+        }
+      },
+    };
+    if !fixed {
+      return None;
+    }
   }
+}
+
+/// Line and column of the value of `raw` that fails with `message`, for the formats that tell them.
+fn locate(raw: &str, format: &str, message: &str) -> Option<(usize, usize)> {
+  let (text, line, column) = match format {
+    "json" => {
+      let err = serde_json::from_str::<objects::Configfile>(raw).err()?;
+      (err.to_string(), err.line(), err.column())
+    },
+    "toml" => {
+      let err = toml::from_str::<objects::Configfile>(raw).err()?;
+      let before = raw.get(..err.span()?.start)?;
+      let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+      (err.message().to_string(), before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    },
+    "yaml" => {
+      let err = serde_yaml::from_str::<objects::Configfile>(raw).err()?;
+      let location = err.location()?;
+      (err.to_string(), location.line(), location.column())
+    },
+    _ => return None,
+  };
+  text.contains(message).then_some((line, column))
+}
+
+/// Reads a config file written in `format`, one of `FORMATS`, without checking its values. Wrong
+/// modes and values of the wrong type are returned as problems, so `check` can find the rest; the
+/// config is only refused when it can't be read at all.
+pub fn read_config(file_name: &str, format: &str) -> Result<(objects::Configfile, Vec<Problem>), ConfigError> {
+  let raw = fs::read_to_string(file_name)?;
+  let mut value: serde_json::Value = parse(&raw, format)?;
+  expand_value(&mut value, "")?;
+  let mut problems = check_modes(&mut value);
+  let mode_problems = problems.len();
+  let info = read_value(&mut value, file_name, &mut problems);
 
-  if info.api_key.trim().is_empty() {
-    valid = false;
+  // the raw file stops at its first wrong value, which is only this one when the modes are right
+  if mode_problems == 0 && let Some(problem) = problems.first_mut()
+    && let Some((line, column)) = locate(&raw, format, &problem.message) {
+    problem.message = format!("{} at line {} column {}", problem.message, line, column);
+  }
+  match info {
+    Some(info) => Ok((info, problems)),
+    None => Err(ConfigError::Invalid(problems)),
+  }
+}
+
+/// Adds the problems `check` finds in `info`, except on the fields already reported by
+/// `read_config`, which were read as placeholders.
+pub fn check_rest(info: &objects::Configfile, problems: &mut Vec<Problem>) {
+  let rest: Vec<Problem> = check(info).into_iter()
+    .filter(|problem| !problems.iter().any(|known| known.field == problem.field))
+    .collect();
+  problems.extend(rest);
+}
+
+/// Problems of a config that is not written yet, expanded like it would be once loaded.
+pub fn check_value(value: &serde_json::Value) -> Result<Vec<Problem>, ConfigError> {
+  let mut value = value.clone();
+  expand_value(&mut value, "")?;
+  let mut problems = check_modes(&mut value);
+  if let Some(info) = read_value(&mut value, "config", &mut problems) {
+    check_rest(&info, &mut problems);
+  }
+  Ok(problems)
+}
+
+/// Loads a config file and refuses it when `check` finds errors, warnings are left to the caller.
+pub fn load_config(file_name: &str, format: &str) -> Result<objects::Configfile, ConfigError> {
+  let (info, mut problems) = read_config(file_name, format)?;
+  check_rest(&info, &mut problems);
+  let errors: Vec<Problem> = problems.into_iter().filter(|problem| !problem.warning).collect();
+  match errors.is_empty() {
+    true => Ok(info),
+    false => Err(ConfigError::Invalid(errors)),
+  }
+}
+
+struct Checker {
+  problems: Vec<Problem>,
+}
+
+impl Checker {
+  fn error(&mut self, field: String, message: String) {
+    self.problems.push(Problem { field, message, warning: false });
+  }
+
+  fn warning(&mut self, field: String, message: String) {
+    self.problems.push(Problem { field, message, warning: true });
+  }
+
+  fn server(&mut self, server: &objects::Server, field: &str) {
+    let prefix = if field.is_empty() { String::new() } else { format!("{}.", field) };
+    if utils::is_local_backend(&server.base_url, &server.backend) {
+      return;
+    }
+    match url::Url::parse(&server.base_url) {
+      Ok(url) if url.scheme() != "http" && url.scheme() != "https" => {
+        self.error(format!("{}base_url", prefix), format!("{} has to start with http:// or https://", server.base_url));
+      },
+      Ok(url) if url.host_str().is_none_or(str::is_empty) => {
+        self.error(format!("{}base_url", prefix), format!("{} has no host", server.base_url));
+      },
+      Ok(_) => {},
+      Err(err) => self.error(format!("{}base_url", prefix), format!("{} is not a valid url: {}", server.base_url, err)),
+    }
+    if !credentials::has_source(server) {
+      self.error(format!("{}api_key", prefix), String::from("no api key, set api_key, api_key_env, api_key_file, api_key_command or api_key_keyring"));
+    }
+  }
+}
+
+/// `local_path` without the trailing slashes, so equal folders compare equal.
+fn folder(local_path: &str) -> &Path {
+  Path::new(local_path.trim_end_matches('/'))
+}
+
+/// Every problem of a loaded config, errors and warnings.
+pub fn check(info: &objects::Configfile) -> Vec<Problem> {
+  let mut checker = Checker { problems: Vec::new() };
+
+  // the top level server is optional when every dir names one of `servers`
+  if !info.server.base_url.trim().is_empty() {
+    checker.server(&info.server, "");
+  }
+  for (name, server) in info.servers.iter() {
+    let field = format!("servers.{}", name);
+    match server.base_url.trim().is_empty() {
+      true => checker.error(format!("{}.base_url", field), String::from("is empty")),
+      false => checker.server(server, &field),
+    }
   }
 
   if info.dirs.is_empty() {
-    valid = false;
+    checker.error(String::from("dirs"), String::from("there are no dirs to sync"));
   }
 
-  for dir in info.dirs.iter() {
+  for (i, dir) in info.dirs.iter().enumerate() {
+    let field = format!("dirs[{}]", i);
+
+    // names must tell dirs apart, and a number would be taken for an index
+    if let Some(j) = info.dirs[..i].iter().position(|other| !dir.name.is_empty() && other.name == dir.name) {
+      checker.error(format!("{}.name", field), format!("{} is also the name of dirs[{}]", dir.name, j));
+    }
+    if dir.name.parse::<usize>().is_ok() {
+      checker.error(format!("{}.name", field), format!("{} is a number, it would be taken for an index", dir.name));
+    }
+
+    if server(info, &dir.server).is_none() {
+      let message = match dir.server.is_empty() {
+        true => String::from("no server, set the top level base_url or name one of servers"),
+        false if info.servers.is_empty() => format!("unknown server {}, the config has no servers", dir.server),
+        false => format!("unknown server {}, use one of {}", dir.server, info.servers.keys().cloned().collect::<Vec<String>>().join(", ")),
+      };
+      checker.error(format!("{}.server", field), message);
+    }
+
     if dir.remote_path.trim().is_empty() {
-      valid = false;
+      checker.error(format!("{}.remote_path", field), String::from("is empty"));
     }
+
     if dir.local_path.trim().is_empty() {
-      valid = false;
+      checker.error(format!("{}.local_path", field), String::from("is empty"));
+      continue;
     }
-    if dir.sync_mode.trim().is_empty() {
-      valid = false;
+    let local_path = folder(&dir.local_path);
+    // dirs that receive files create their folder on the first sync
+    match local_path.metadata() {
+      Ok(metadata) if !metadata.is_dir() => checker.error(format!("{}.local_path", field), format!("{} is not a folder", dir.local_path)),
+      Err(_) if !compare::receives(dir.sync_mode) => checker.warning(format!("{}.local_path", field), format!("{} does not exist", dir.local_path)),
+      _ => {},
+    }
+
+    for (j, other) in info.dirs[..i].iter().enumerate() {
+      let other_path = folder(&other.local_path);
+      if other.local_path.trim().is_empty() {
+        continue;
+      }
+      // sending one folder to several places is fine, receiving into it from several is not
+      if local_path == other_path && (compare::receives(dir.sync_mode) || compare::receives(other.sync_mode)) {
+        checker.error(format!("{}.local_path", field), format!("{} is also the local path of dirs[{}]", dir.local_path, j));
+      } else if local_path != other_path && (local_path.starts_with(other_path) || other_path.starts_with(local_path)) {
+        checker.warning(format!("{}.local_path", field), format!("{} overlaps with {} of dirs[{}]", dir.local_path, other.local_path, j));
+      }
+    }
+  }
+
+  checker.problems
+}
+
+/// TOML has no null, keys set to null are left out like they were never written.
+fn strip_nulls(value: &mut serde_json::Value) {
+  match value {
+    serde_json::Value::Object(map) => {
+      map.retain(|_, value| !value.is_null());
+      map.values_mut().for_each(strip_nulls);
+    },
+    serde_json::Value::Array(list) => list.iter_mut().for_each(strip_nulls),
+    _ => {},
+  }
+}
+
+/// Rewrites a config file in another format. Only what the file sets is written, the defaults
+/// stay implicit, but comments are lost.
+pub fn convert(file_name: &str, from: &str, to: &str) -> Result<String, ConfigError> {
+  load_config(file_name, from)?;
+  let raw = fs::read_to_string(file_name)?;
+  render(parse(&raw, from)?, to)
+}
+
+/// Writes a config in `format`, one of `FORMATS`.
+pub fn render(mut value: serde_json::Value, format: &str) -> Result<String, ConfigError> {
+  match format {
+    "json" => Ok(format!("{}\n", serde_json::to_string_pretty(&value)?)),
+    "toml" => {
+      strip_nulls(&mut value);
+      Ok(toml::to_string_pretty(&value)?)
+    },
+    "yaml" => Ok(serde_yaml::to_string(&value)?),
+    _ => Err(ConfigError::UnknownFormat(format.to_string())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the tests only read variables, HOME is set and this one never is
+  const UNSET: &str = "KCLOUD_SYNC_TEST_UNSET";
+
+  fn home() -> String {
+    env::var("HOME").unwrap()
+  }
+
+  #[test]
+  fn variables_and_home_are_expanded() {
+    assert_eq!(expand_vars("~/sync", "f").unwrap(), format!("{}/sync", home()));
+    assert_eq!(expand_vars("$HOME/a:${HOME}", "f").unwrap(), format!("{}/a:{}", home(), home()));
+    assert_eq!(expand_vars("a~/b", "f").unwrap(), "a~/b");
+  }
+
+  #[test]
+  fn defaults_are_used_for_unset_variables() {
+    assert_eq!(expand_vars(&format!("${{{}:-/backup}}/x", UNSET), "f").unwrap(), "/backup/x");
+    assert_eq!(expand_vars("${HOME:-/backup}", "f").unwrap(), home());
+  }
+
+  #[test]
+  fn double_dollar_is_a_plain_dollar() {
+    assert_eq!(expand_vars("pa$$word$$HOME", "f").unwrap(), "pa$word$HOME");
+    assert_eq!(expand_vars("costs 5$", "f").unwrap(), "costs 5$");
+  }
+
+  #[test]
+  fn unset_variables_are_errors() {
+    match expand_vars(&format!("/data/${}", UNSET), "dirs[0].local_path") {
+      Err(ConfigError::UnsetVariable { variable, field }) => {
+        assert_eq!(variable, UNSET);
+        assert_eq!(field, "dirs[0].local_path");
+      },
+      other => panic!("unexpected {:?}", other),
     }
+    assert!(expand_vars("${HOME", "f").is_err());
+  }
+
+  #[test]
+  fn api_keys_and_passphrases_are_kept_as_written() {
+    let mut value = serde_json::json!({
+      "api_key": "se$cret",
+      "api_key_file": "~/key",
+      "dirs": [{ "local_path": "~/$$x", "encryption": { "passphrase": "$HOME" } }],
+    });
+    expand_value(&mut value, "").unwrap();
+    assert_eq!(value["api_key"], "se$cret");
+    assert_eq!(value["api_key_file"], format!("{}/key", home()));
+    assert_eq!(value["dirs"][0]["local_path"], format!("{}/$x", home()));
+    assert_eq!(value["dirs"][0]["encryption"]["passphrase"], "$HOME");
   }
 
-  match valid {
-    true => return Ok(info),
-    false => return Err(Error::custom("Invalid config file")),
-      
+  #[test]
+  fn escaped_values_expand_to_what_was_typed() {
+    let mut value = serde_json::json!({ "api_key": "a$b", "dirs": [{ "local_path": "/data/$HOME" }] });
+    escape_value(&mut value, "");
+    expand_value(&mut value, "").unwrap();
+    assert_eq!(value, serde_json::json!({ "api_key": "a$b", "dirs": [{ "local_path": "/data/$HOME" }] }));
   }
 }
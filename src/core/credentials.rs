@@ -0,0 +1,79 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use crate::core::api_conn::ApiError;
+use crate::core::objects::Server;
+
+/// Service the keyring entries are stored under, with the entry name as `account`.
+pub const KEYRING_SERVICE: &str = "k-cloud-sync";
+
+fn credentials_error(reason: String) -> ApiError {
+  ApiError::Credentials(reason)
+}
+
+/// Tells whether `server` has somewhere to take the api key from.
+pub fn has_source(server: &Server) -> bool {
+  [&server.api_key, &server.api_key_env, &server.api_key_file, &server.api_key_command, &server.api_key_keyring]
+    .iter()
+    .any(|source| !source.trim().is_empty())
+}
+
+fn from_file(path: &str) -> Result<String, ApiError> {
+  let mode = fs::metadata(path)?.permissions().mode();
+  if mode & 0o007 != 0 {
+    return Err(credentials_error(format!("{} can be accessed by other users, run chmod 600 {}", path, path)));
+  }
+  Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Runs `command` with the shell, the key is the first line it prints.
+fn run_command(command: &mut Command, label: &str) -> Result<String, ApiError> {
+  let output = command.output().map_err(|err| credentials_error(format!("{} could not run: {}", label, err)))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr).chars().take(200).collect::<String>();
+    return Err(credentials_error(format!("{} failed ({}): {}", label, output.status, stderr.trim())));
+  }
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  Ok(stdout.lines().next().unwrap_or("").trim().to_string())
+}
+
+fn from_keyring(entry: &str) -> Result<String, ApiError> {
+  let mut command = Command::new("secret-tool");
+  command.args(["lookup", "service", KEYRING_SERVICE, "account", entry]);
+  run_command(&mut command, &format!("keyring lookup of {}", entry))
+}
+
+/// The api key of `server`, from the first source that has one: `api_key_env` (when the variable
+/// is set), `api_key`, `api_key_file`, `api_key_command` and `api_key_keyring`.
+pub fn api_key(server: &Server) -> Result<String, ApiError> {
+  if !server.api_key_env.is_empty() && let Ok(key) = env::var(&server.api_key_env) && !key.trim().is_empty() {
+    return Ok(key.trim().to_string());
+  }
+
+  let key = if !server.api_key.trim().is_empty() {
+    server.api_key.trim().to_string()
+  } else if !server.api_key_file.is_empty() {
+    from_file(&server.api_key_file)?
+  } else if !server.api_key_command.is_empty() {
+    let mut command = Command::new("sh");
+    command.args(["-c", &server.api_key_command]);
+    run_command(&mut command, "api_key_command")?
+  } else if !server.api_key_keyring.is_empty() {
+    from_keyring(&server.api_key_keyring)?
+  } else if !server.api_key_env.is_empty() {
+    return Err(credentials_error(format!("{} is not set", server.api_key_env)));
+  } else {
+    return Err(credentials_error(String::from("no api key configured")));
+  };
+
+  if key.is_empty() {
+    return Err(credentials_error(String::from("the api key is empty")));
+  }
+  Ok(key)
+}
@@ -0,0 +1,350 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use aes_siv::siv::Aes256Siv;
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha512};
+use crate::core::api_conn::ApiError;
+use crate::core::file_conn;
+use crate::core::objects::Encryption;
+
+// Encrypted file layout:
+// magic (4) | version (1) | kdf (1) | salt (16) | nonce prefix (19) | segments
+// every segment holds SEGMENT_SIZE bytes of plaintext plus a 16 byte tag, the last one may be shorter.
+const MAGIC: &[u8; 4] = b"KCSE";
+const VERSION: u8 = 1;
+const KDF_KEY_FILE: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 19;
+const TAG_SIZE: u64 = 16;
+pub const HEADER_SIZE: u64 = (4 + 1 + 1 + SALT_SIZE + NONCE_SIZE) as u64;
+pub const SEGMENT_SIZE: u64 = 1048576;
+
+// names must encrypt the same way on every machine, so their key can't use a random salt
+const NAMES_SALT: &[u8; 16] = b"k-cloud-sync/nms";
+
+type Key = [u8; 32];
+
+/// Size of the remote copy of a local file of `size` bytes.
+pub fn encrypted_size(size: u64) -> u64 {
+  let segments = size.div_ceil(SEGMENT_SIZE).max(1);
+  HEADER_SIZE + size + segments * TAG_SIZE
+}
+
+fn crypto_error(reason: &str) -> ApiError {
+  ApiError::Crypto(reason.to_string())
+}
+
+fn read_key_file(path: &str) -> Result<Key, ApiError> {
+  let raw = fs::read(path)?;
+  let text = String::from_utf8_lossy(&raw);
+  let hex = text.trim();
+
+  if raw.len() == 32 {
+    let mut key: Key = [0u8; 32];
+    key.copy_from_slice(&raw);
+    return Ok(key);
+  }
+  if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    let mut key: Key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+      *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| crypto_error("invalid key file"))?;
+    }
+    return Ok(key);
+  }
+  Err(crypto_error("key file must hold 32 raw bytes or 64 hex characters"))
+}
+
+/// Encrypts and decrypts whole files with XChaCha20-Poly1305 in the STREAM construction.
+pub struct Cipher {
+  passphrase: Option<String>,
+  key_file_key: Option<Key>,
+  upload_salt: [u8; SALT_SIZE],
+  derived: HashMap<[u8; SALT_SIZE], Key>,
+}
+
+impl Cipher {
+  pub fn new(encryption: &Encryption) -> Result<Self, ApiError> {
+    let mut upload_salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut upload_salt);
+
+    let key_file_key = match encryption.key_file.is_empty() {
+      true => None,
+      false => Some(read_key_file(&encryption.key_file)?),
+    };
+    let passphrase = match encryption.passphrase.is_empty() {
+      true => None,
+      false => Some(encryption.passphrase.clone()),
+    };
+    if key_file_key.is_none() && passphrase.is_none() {
+      return Err(crypto_error("encryption needs a passphrase or a key_file"));
+    }
+
+    Ok(Self { passphrase, key_file_key, upload_salt, derived: HashMap::new() })
+  }
+
+  // Argon2 is slow on purpose, so every salt is derived only once per run.
+  fn key_for(&mut self, kdf: u8, salt: &[u8; SALT_SIZE]) -> Result<Key, ApiError> {
+    if kdf == KDF_KEY_FILE {
+      return self.key_file_key.ok_or_else(|| crypto_error("file was encrypted with a key file"));
+    }
+    if let Some(key) = self.derived.get(salt) {
+      return Ok(*key);
+    }
+    let passphrase = self.passphrase.as_ref().ok_or_else(|| crypto_error("file was encrypted with a passphrase"))?;
+    let mut key: Key = [0u8; 32];
+    Argon2::default()
+      .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+      .map_err(|err| crypto_error(&err.to_string()))?;
+    self.derived.insert(*salt, key);
+    Ok(key)
+  }
+
+  /// Encrypts `source` while it is read, the header comes first.
+  pub fn encrypt_reader<R: Read>(&mut self, mut source: R) -> Result<EncryptReader<R>, ApiError> {
+    let kdf = if self.key_file_key.is_some() { KDF_KEY_FILE } else { KDF_ARGON2ID };
+    let salt = self.upload_salt;
+    let key = self.key_for(kdf, &salt)?;
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut output: Vec<u8> = Vec::with_capacity(HEADER_SIZE as usize);
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&[VERSION, kdf]);
+    output.extend_from_slice(&salt);
+    output.extend_from_slice(&nonce);
+
+    let aead = XChaCha20Poly1305::new(&key.into());
+    let mut pending = vec![0u8; SEGMENT_SIZE as usize];
+    let filled = file_conn::read_chunk(&mut source, &mut pending)?;
+    pending.truncate(filled);
+
+    Ok(EncryptReader { source, encryptor: Some(EncryptorBE32::from_aead(aead, &nonce.into())), pending, output, position: 0 })
+  }
+
+  /// Decrypts `source` while it is read, checking its header first.
+  pub fn decrypt_reader<R: Read>(&mut self, mut source: R) -> Result<DecryptReader<R>, ApiError> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    let filled = file_conn::read_chunk(&mut source, &mut header)?;
+    if filled < header.len() || &header[..4] != MAGIC || header[4] != VERSION {
+      return Err(crypto_error("file is not encrypted"));
+    }
+    let kdf = header[5];
+    let mut salt = [0u8; SALT_SIZE];
+    salt.copy_from_slice(&header[6..6 + SALT_SIZE]);
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce.copy_from_slice(&header[6 + SALT_SIZE..]);
+
+    let key = self.key_for(kdf, &salt)?;
+    let aead = XChaCha20Poly1305::new(&key.into());
+    let mut pending = vec![0u8; (SEGMENT_SIZE + TAG_SIZE) as usize];
+    let filled = file_conn::read_chunk(&mut source, &mut pending)?;
+    pending.truncate(filled);
+
+    Ok(DecryptReader { source, decryptor: Some(DecryptorBE32::from_aead(aead, &nonce.into())), pending, output: Vec::new(), position: 0 })
+  }
+}
+
+/// Reads the next block of `block_size` bytes into `pending` and returns the previous one, which
+/// is the last one when nothing follows it.
+fn next_block<R: Read>(source: &mut R, pending: &mut Vec<u8>, block_size: usize) -> io::Result<(Vec<u8>, bool)> {
+  if pending.len() < block_size {
+    return Ok((std::mem::take(pending), true));
+  }
+  let mut next = vec![0u8; block_size];
+  let filled = file_conn::read_chunk(source, &mut next)?;
+  next.truncate(filled);
+  let current = std::mem::replace(pending, next);
+  Ok((current, pending.is_empty()))
+}
+
+/// Encrypted copy of a plaintext reader, one segment is kept in memory at a time.
+pub struct EncryptReader<R: Read> {
+  source: R,
+  encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+  /// Plaintext read ahead, a segment is only known to be the last once nothing follows it.
+  pending: Vec<u8>,
+  output: Vec<u8>,
+  position: usize,
+}
+
+impl<R: Read> Read for EncryptReader<R> {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    if self.position == self.output.len() {
+      let encryptor = match self.encryptor.take() {
+        Some(encryptor) => encryptor,
+        None => return Ok(0),
+      };
+      let (segment, last) = next_block(&mut self.source, &mut self.pending, SEGMENT_SIZE as usize)?;
+      let sealed = match last {
+        true => encryptor.encrypt_last(segment.as_slice()),
+        false => {
+          let mut encryptor = encryptor;
+          let sealed = encryptor.encrypt_next(segment.as_slice());
+          self.encryptor = Some(encryptor);
+          sealed
+        },
+      };
+      self.output = sealed.map_err(|_| io::Error::other("unable to encrypt"))?;
+      self.position = 0;
+    }
+
+    let length = buffer.len().min(self.output.len() - self.position);
+    buffer[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+    self.position += length;
+    Ok(length)
+  }
+}
+
+/// Plaintext of an encrypted reader, every segment is authenticated before it is returned.
+pub struct DecryptReader<R: Read> {
+  source: R,
+  decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+  pending: Vec<u8>,
+  output: Vec<u8>,
+  position: usize,
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    while self.position == self.output.len() {
+      let decryptor = match self.decryptor.take() {
+        Some(decryptor) => decryptor,
+        None => return Ok(0),
+      };
+      let (segment, last) = next_block(&mut self.source, &mut self.pending, (SEGMENT_SIZE + TAG_SIZE) as usize)?;
+      let plain = match last {
+        true => decryptor.decrypt_last(segment.as_slice()),
+        false => {
+          let mut decryptor = decryptor;
+          let plain = decryptor.decrypt_next(segment.as_slice());
+          self.decryptor = Some(decryptor);
+          plain
+        },
+      };
+      self.output = plain.map_err(|_| io::Error::other("wrong key or corrupted file"))?;
+      self.position = 0;
+    }
+
+    let length = buffer.len().min(self.output.len() - self.position);
+    buffer[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+    self.position += length;
+    Ok(length)
+  }
+}
+
+/// Deterministic AES-SIV encryption of path segments, encoded as lowercase base32.
+pub struct NameCipher {
+  siv: Aes256Siv,
+  max_name_length: usize,
+}
+
+impl NameCipher {
+  pub fn new(encryption: &Encryption) -> Result<Self, ApiError> {
+    let mut key = [0u8; 64];
+
+    if !encryption.key_file.is_empty() {
+      let mut hasher = Sha512::new();
+      hasher.update(read_key_file(&encryption.key_file)?);
+      hasher.update(NAMES_SALT);
+      key.copy_from_slice(&hasher.finalize());
+    } else {
+      Argon2::default()
+        .hash_password_into(encryption.passphrase.as_bytes(), NAMES_SALT, &mut key)
+        .map_err(|err| crypto_error(&err.to_string()))?;
+    }
+
+    Ok(Self { siv: Aes256Siv::new(&key.into()), max_name_length: encryption.max_name_length })
+  }
+
+  pub fn encrypt_name(&mut self, name: &str) -> Result<String, ApiError> {
+    let sealed = self.siv.encrypt([[0u8; 0]; 0], name.as_bytes()).map_err(|_| crypto_error("unable to encrypt name"))?;
+    let encoded = BASE32_NOPAD.encode(&sealed).to_lowercase();
+
+    if encoded.len() > self.max_name_length {
+      return Err(ApiError::NameTooLong { name: name.to_string(), length: encoded.len(), max: self.max_name_length });
+    }
+    Ok(encoded)
+  }
+
+  /// Returns `None` for names that were not encrypted with this key.
+  pub fn decrypt_name(&mut self, encoded: &str) -> Option<String> {
+    let sealed = BASE32_NOPAD.decode(encoded.to_uppercase().as_bytes()).ok()?;
+    let plain = self.siv.decrypt([[0u8; 0]; 0], &sealed).ok()?;
+    String::from_utf8(plain).ok()
+  }
+
+  pub fn encrypt_path(&mut self, virtual_path: &str) -> Result<String, ApiError> {
+    let mut segments: Vec<String> = Vec::new();
+    for segment in virtual_path.split('/').filter(|segment| !segment.is_empty()) {
+      segments.push(self.encrypt_name(segment)?);
+    }
+    Ok(segments.join("/"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encryption(passphrase: &str, key_file: &str) -> Encryption {
+    Encryption { passphrase: passphrase.to_string(), key_file: key_file.to_string(), encrypt_names: false, max_name_length: 255 }
+  }
+
+  fn encrypt(cipher: &mut Cipher, plain: &[u8]) -> Vec<u8> {
+    let mut encrypted = Vec::new();
+    cipher.encrypt_reader(plain).unwrap().read_to_end(&mut encrypted).unwrap();
+    encrypted
+  }
+
+  fn decrypt(cipher: &mut Cipher, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+    let mut plain = Vec::new();
+    cipher.decrypt_reader(encrypted).map_err(io::Error::other)?.read_to_end(&mut plain)?;
+    Ok(plain)
+  }
+
+  #[test]
+  fn round_trip_keeps_the_content() {
+    let key_file = std::env::temp_dir().join(format!("kcloud-test-key-{}", std::process::id()));
+    fs::write(&key_file, [7u8; 32]).unwrap();
+    let mut cipher = Cipher::new(&encryption("", &key_file.display().to_string())).unwrap();
+    let _ = fs::remove_file(&key_file);
+
+    let segment = SEGMENT_SIZE as usize;
+    for size in [0, 1, segment - 1, segment, 2 * segment + 5] {
+      let plain: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+      let encrypted = encrypt(&mut cipher, &plain);
+      assert_eq!(encrypted.len() as u64, encrypted_size(size as u64), "size {}", size);
+      assert_eq!(decrypt(&mut cipher, &encrypted).unwrap(), plain, "size {}", size);
+    }
+  }
+
+  #[test]
+  fn wrong_passphrase_is_rejected() {
+    let plain = b"some private content".to_vec();
+    let encrypted = encrypt(&mut Cipher::new(&encryption("right passphrase", "")).unwrap(), &plain);
+
+    let mut wrong = Cipher::new(&encryption("wrong passphrase", "")).unwrap();
+    assert!(decrypt(&mut wrong, &encrypted).is_err());
+  }
+
+  #[test]
+  fn tampered_segment_is_rejected() {
+    let mut cipher = Cipher::new(&encryption("passphrase", "")).unwrap();
+    let mut encrypted = encrypt(&mut cipher, &[1u8; 100]);
+    let last = encrypted.len() - 1;
+    encrypted[last] ^= 1;
+    assert!(decrypt(&mut cipher, &encrypted).is_err());
+  }
+}
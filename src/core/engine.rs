@@ -3,144 +3,696 @@
  * Copyright(c) Kintaro Ponce
  * MIT Licensed
  */
-use crate::core::{file_conn, utils::{self}};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::core::{backup, compare, compression, crypto, file_conn, output, sync_state, trash, versions, utils::{self}};
+use crate::core::compare::Decision;
 use async_recursion::async_recursion;
 pub use crate::core::objects;
-pub use crate::core::api_conn;
+use crate::core::objects::{CompareMode, EntryType, SyncMode};
+pub use crate::core::remote;
+use crate::core::api_conn::{ApiError, CHUNK_SIZE};
 
+#[derive(Debug, PartialEq)]
+pub enum Action {
+  CreateLocalDir,
+  CreateRemoteDir,
+  Download,
+  Upload,
+  DeleteLocal,
+  DeleteRemote,
+  Unchanged,
+  Conflict,
+}
 
-#[async_recursion]
-async fn get_files(dirs: &objects::Dirsync, api_client: &api_conn::ApiClient, virtual_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-  let local_path = &dirs.local_path;
-  let remote_path = &dirs.remote_path;
+pub struct PlanItem {
+  pub action: Action,
+  pub virtual_path: String,
+  pub size: u64,
+  pub remote_size: Option<u64>,
+}
 
-  if !file_conn::file_exists(&local_path) && virtual_path == "" {
-    file_conn::create_dir(&local_path);
-    println!("dir created {}",local_path);
-  }
+/// Everything a single `Dirsync` run needs, loaded once and saved at the end.
+pub struct SyncContext<'a> {
+  pub dir: &'a objects::Dirsync,
+  pub remote: &'a remote::Remote,
+  /// Remote folder the virtual paths are relative to, `dir.remote_path` unless a snapshot is being written.
+  pub remote_root: String,
+  pub state: sync_state::SyncState,
+  pub hashes: sync_state::HashCache,
+  pub cipher: Option<crypto::Cipher>,
+  pub names: Option<crypto::NameCipher>,
+  /// Only report what would change, nothing is transferred or deleted.
+  pub dry_run: bool,
+  /// Local entries left out of the plan because they can't be created on the server.
+  pub rejected: u32,
+}
 
-  let virtual_local_path: String;
-  let virtual_remote_path: String;
+impl<'a> SyncContext<'a> {
+  pub fn new(dir: &'a objects::Dirsync, remote: &'a remote::Remote) -> Result<Self, ApiError> {
+    let cipher = match &dir.encryption {
+      Some(encryption) => Some(crypto::Cipher::new(encryption)?),
+      None => None,
+    };
+    let names = match &dir.encryption {
+      Some(encryption) if encryption.encrypt_names => Some(crypto::NameCipher::new(encryption)?),
+      _ => None,
+    };
 
-  if virtual_path.is_empty() {
-    virtual_local_path = local_path.clone();
-    virtual_remote_path = remote_path.clone();    
-  } else {
-    virtual_local_path = utils::create_path(local_path, virtual_path);
-    virtual_remote_path = utils::create_path(remote_path, virtual_path);
+    Ok(Self {
+      dir,
+      remote,
+      remote_root: dir.remote_path.clone(),
+      state: sync_state::load_state(dir),
+      hashes: sync_state::load_hash_cache(dir),
+      cipher,
+      names,
+      dry_run: false,
+      rejected: 0,
+    })
   }
 
-  let files_server_list = api_client.get_files_list(&virtual_remote_path).await.unwrap();
+  pub fn local_path(&self, virtual_path: &str) -> String {
+    utils::side_path(&self.dir.local_path, virtual_path)
+  }
 
-  for file in files_server_list.list.iter() {
-    let file_virtual_path_server = utils::create_path(&virtual_remote_path, &file.name);
-    let file_virtual_path_local = utils::create_path(&virtual_local_path, &file.name);
-    if file.r#type == "folder" {
-      if !file_conn::file_exists(&file_virtual_path_local) {
-        file_conn::create_dir(&file_virtual_path_local);
-        println!("dir created {}",utils::create_path(virtual_path, &file.name));
-      }
-      let _ = get_files(dirs, api_client, &utils::create_path(virtual_path, &file.name)).await;
-
-    } else {
-      if !file_conn::file_exists(&file_virtual_path_local) {
-        println!("downloading {}",utils::create_path(virtual_path, &file.name));
-        let mut file_local = file_conn::create_file_stream(&file_virtual_path_local);
-        let _ = api_client.get_file(&file_virtual_path_server, &mut file_local).await;
-        println!("downloaded  {}",utils::create_path(virtual_path, &file.name));
-        // drop(file_local);
-      } else {
-        println!("found {}",utils::create_path(virtual_path, &file.name));
+  pub fn remote_path(&mut self, virtual_path: &str) -> Result<String, ApiError> {
+    let root = self.remote_root.clone();
+    if self.lists_snapshots() {
+      // snapshot folders are named after their time, only the paths inside them are encrypted
+      let (stamp, inner_path) = virtual_path.trim_start_matches('/').split_once('/').unwrap_or((virtual_path, ""));
+      if !stamp.is_empty() {
+        return self.remote_path_in(&utils::create_path(&root, stamp), inner_path);
       }
-      // println!("{} -> {}\n", file_virtual_path_server, file_virtual_path_local);
     }
+    self.remote_path_in(&root, virtual_path)
+  }
+
+  /// Tells whether the remote root holds the snapshot folders of a `backup` dir.
+  fn lists_snapshots(&self) -> bool {
+    self.dir.sync_mode == SyncMode::Backup && self.remote_root == self.dir.remote_path
+  }
+
+  pub fn remote_path_in(&mut self, root: &str, virtual_path: &str) -> Result<String, ApiError> {
+    let remote_virtual_path = match self.names.as_mut() {
+      Some(names) => names.encrypt_path(virtual_path)?,
+      None => virtual_path.to_string(),
+    };
+    Ok(utils::side_path(root, &remote_virtual_path))
+  }
+
+  /// Lists a remote folder with the names as the user sees them.
+  pub async fn remote_list(&mut self, virtual_path: &str) -> Result<Vec<objects::File>, ApiError> {
+    let remote_path = self.remote_path(virtual_path)?;
+    let mut list = self.remote.get_files_list(&remote_path).await?.list;
+
+    let snapshot_names = self.lists_snapshots() && virtual_path.trim_matches('/').is_empty();
+    if let Some(names) = self.names.as_mut() && !snapshot_names {
+      list.retain_mut(|file| utils::is_reserved_name(&file.name) || match names.decrypt_name(&file.name) {
+        Some(name) => {
+          file.name = name;
+          true
+        },
+        None => {
+          crate::progress!("skipped   {} (name not encrypted with this key)", utils::create_path(virtual_path, &file.name));
+          false
+        },
+      });
+    }
+    Ok(list)
+  }
+
+  pub fn local_hash(&mut self, virtual_path: &str, stat: &objects::FileStat) -> Result<Option<String>, ApiError> {
+    if self.dir.compare_mode != CompareMode::Checksum {
+      return Ok(None);
+    }
+    let local_path = self.local_path(virtual_path);
+    Ok(Some(self.hashes.hash(&local_path, stat, self.dir.hash_algorithm)?))
+  }
+
+  fn record(&mut self, virtual_path: &str, remote_size: Option<u64>) -> Result<(), ApiError> {
+    let stat = file_conn::get_file_stat(&self.local_path(virtual_path))?;
+    let hash = self.local_hash(virtual_path, &stat)?;
+    let record = sync_state::record_from_stat(&stat, hash, remote_size);
+    self.state.files.insert(virtual_path.to_string(), record);
+    Ok(())
+  }
+
+  /// Drops the records of `virtual_path` and everything below it.
+  fn forget(&mut self, virtual_path: &str) {
+    let folder_prefix = format!("{}/", virtual_path);
+    self.state.files.retain(|path, _| path != virtual_path && !path.starts_with(&folder_prefix));
+    self.state.chunks.retain(|path, _| path != virtual_path && !path.starts_with(&folder_prefix));
+  }
+
+  /// Tells whether `virtual_path`, or something below it, was synced by a previous run.
+  fn was_synced(&self, virtual_path: &str) -> bool {
+    let folder_prefix = format!("{}/", virtual_path);
+    self.state.files.keys().any(|path| path == virtual_path || path.starts_with(&folder_prefix))
+  }
+
+  /// Delta uploads need the exact bytes on the server, so only large plain files qualify.
+  fn delta_eligible(&self, size: u64) -> bool {
+    self.dir.delta && self.cipher.is_none() && self.dir.compression.is_none() && size >= SMALL_FILE_LIMIT
+  }
+
+  /// Keeps the block hashes of an uploaded file, `hashes` when the delta upload already computed them.
+  fn record_chunks(&mut self, virtual_path: &str, size: u64, hashes: Option<Vec<String>>) -> Result<(), ApiError> {
+    if !self.delta_eligible(size) {
+      self.state.chunks.remove(virtual_path);
+      return Ok(());
+    }
+    let hashes = match hashes {
+      Some(hashes) => hashes,
+      None => file_conn::chunk_hashes(&self.local_path(virtual_path), CHUNK_SIZE)?,
+    };
+    self.state.chunks.insert(virtual_path.to_string(), hashes);
+    Ok(())
+  }
+
+  pub fn save(&mut self) -> Result<(), ApiError> {
+    sync_state::save_state(self.dir, &self.state)?;
+    sync_state::save_hash_cache(self.dir, &self.hashes)?;
+    Ok(())
+  }
+}
+
+async fn download_file(remote_client: &remote::Remote, remote_path: &str, local_path: &str) -> Result<u64, ApiError> {
+  let properties = remote_client.get_file_properties(remote_path).await?;
+  let part_path = utils::part_path(local_path);
+  let file_local = file_conn::create_file_stream(&part_path);
+  let received = match remote_client.get_file(remote_path, &file_local).await {
+    Ok(received) => received,
+    Err(err) => {
+      let _ = file_conn::remove_file(&part_path);
+      return Err(err);
+    }
+  };
+  drop(file_local);
+
+  let mut reason: Option<String> = None;
+  if let Some(size) = properties.size && size != received {
+    reason = Some(format!("expected {} bytes, received {}", size, received));
+  }
+  if let (None, Some(expected)) = (&reason, &properties.sha256) {
+    let actual = file_conn::hash_file(&part_path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+      reason = Some(format!("expected sha256 {}, got {}", expected, actual));
+    }
+  }
+
+  if let Some(reason) = reason {
+    let _ = file_conn::remove_file(&part_path);
+    return Err(ApiError::Integrity { path: remote_path.to_string(), reason });
+  }
+
+  file_conn::rename_file(&part_path, local_path)?;
+  Ok(received)
+}
+
+const UPLOAD_ATTEMPTS: u8 = 3;
+
+/// Content read while it is transferred, compressed or encrypted on the way.
+type Stream = Box<dyn Read + Send>;
+
+const SMALL_FILE_LIMIT: u64 = 104857600;
+
+async fn transfer_file(remote_client: &remote::Remote, local_path: &str, remote_path: &str, size: u64, virtual_path: &str) -> Result<(), ApiError> {
+  if size < SMALL_FILE_LIMIT {
+    remote_client.upload_small_file(remote_path, local_path).await?;
+    return Ok(())
   }
 
+  remote_client.initialize_file(remote_path, size).await?;
+  remote_client.upload_file_chunks(remote_path, local_path, size, virtual_path).await?;
   Ok(())
 }
 
-async fn upload_file(api_client: &api_conn::ApiClient, local_path: &String, remote_path: &String, size: u64, virtual_path: &String) {
-  if size < 104857600 {
-    let _ = api_client.upload_small_file(remote_path, local_path).await.unwrap();
+/// Checks the size and the sha256 the server reports, `sent_hash` is the hash of what was sent
+/// when it was not `local_path` as it is. A server that reports neither only gets a warning.
+async fn verify_upload(remote_client: &remote::Remote, local_path: &str, remote_path: &str, size: u64, sent_hash: Option<String>) -> Result<(), ApiError> {
+  let properties = remote_client.get_file_properties(remote_path).await?;
+  if properties.size.is_none() && properties.sha256.is_none() {
+    output::warning(format!("{}: upload not verified, the server reports neither its size nor its sha256", remote_path));
+    return Ok(());
+  }
+
+  let mut reason: Option<String> = None;
+  if let Some(remote_size) = properties.size && remote_size != size {
+    reason = Some(format!("expected {} bytes, server has {}", size, remote_size));
+  }
+  if let (None, Some(remote_hash)) = (&reason, &properties.sha256) {
+    let local_hash = match sent_hash {
+      Some(hash) => hash,
+      None => file_conn::hash_file(local_path)?,
+    };
+    if !local_hash.eq_ignore_ascii_case(remote_hash) {
+      reason = Some(format!("expected sha256 {}, server has {}", local_hash, remote_hash));
+    }
+  }
+
+  match reason {
+    Some(reason) => Err(ApiError::Integrity { path: remote_path.to_string(), reason }),
+    None => Ok(()),
+  }
+}
+
+/// Reader over the compressed and encrypted content of `local_path` and its size,
+/// `None` when the file is sent as it is.
+fn upload_stream(ctx: &mut SyncContext<'_>, local_path: &str, size: u64, virtual_path: &str) -> Result<Option<(Stream, u64)>, ApiError> {
+  let mut stream: Stream = Box::new(File::open(local_path)?);
+  let mut stream_size = size;
+  let mut transformed = false;
 
-    return ()
+  if let Some(compression) = &ctx.dir.compression {
+    let compressed = match compression::applies(compression, virtual_path) {
+      true => Some(compression::compressed_copy(compression, local_path)?),
+      false => None,
+    };
+    // skipped extensions and incompressible content are sent as they are, behind the raw marker
+    stream = match compressed.filter(|(_, compressed_size)| *compressed_size < size + compression::MARKER_SIZE) {
+      Some((copy, compressed_size)) => {
+        stream_size = compressed_size;
+        Box::new(copy)
+      },
+      None => {
+        stream_size = size + compression::MARKER_SIZE;
+        compression::raw_reader(stream)
+      },
+    };
+    transformed = true;
   }
+  if let Some(cipher) = ctx.cipher.as_mut() {
+    stream = Box::new(cipher.encrypt_reader(stream)?);
+    stream_size = crypto::encrypted_size(stream_size);
+    transformed = true;
+  }
+
+  Ok(transformed.then_some((stream, stream_size)))
+}
+
+async fn send_once(ctx: &mut SyncContext<'_>, local_path: &str, remote_path: &str, size: u64, virtual_path: &str) -> Result<u64, ApiError> {
+  let (sent_size, sent_hash) = match upload_stream(ctx, local_path, size, virtual_path)? {
+    Some((mut stream, stream_size)) => {
+      let hash = ctx.remote.upload_reader(remote_path, stream.as_mut(), stream_size, virtual_path).await?;
+      (stream_size, Some(hash))
+    },
+    None => {
+      transfer_file(ctx.remote, local_path, remote_path, size, virtual_path).await?;
+      (size, None)
+    },
+  };
 
-  let initialize_result = api_client.initialize_file(remote_path, size).await;
-  match initialize_result {
-      Ok(_) => {
-        let _ = api_client.upload_file_chunks(remote_path, local_path, size, virtual_path).await.unwrap();
+  verify_upload(ctx.remote, local_path, remote_path, sent_size, sent_hash).await?;
+  Ok(sent_size)
+}
+
+/// Uploads a local file, compressing and encrypting it on the way when the dir asks for it. Returns the remote size.
+pub async fn send_file(ctx: &mut SyncContext<'_>, local_path: &str, remote_path: &str, size: u64, virtual_path: &str) -> Result<u64, ApiError> {
+  let mut attempt: u8 = 1;
+
+  loop {
+    match send_once(ctx, local_path, remote_path, size, virtual_path).await {
+      Ok(sent_size) => return Ok(sent_size),
+      Err(err) if attempt < UPLOAD_ATTEMPTS => {
+        crate::progress!("retrying   {} ({})", virtual_path, err);
+        attempt += 1;
       },
-      Err(err) => {
-        println!("{}", err);
-      }
+      Err(err) => return Err(err),
+    }
   }
 }
 
+/// Indexes of the chunks of `new_hashes` that differ from `old_hashes`, those past the old end included.
+fn changed_chunks(old_hashes: &[String], new_hashes: &[String]) -> Vec<usize> {
+  new_hashes.iter().enumerate()
+    .filter(|(index, hash)| old_hashes.get(*index) != Some(*hash))
+    .map(|(index, _)| index)
+    .collect()
+}
+
+/// Rewrites only the chunks of a large file that changed since its last upload.
+/// Returns the remote size and the new block hashes, or `None` when the file has to be sent in full.
+async fn delta_upload(ctx: &mut SyncContext<'_>, local_path: &str, remote_path: &str, item: &PlanItem) -> Result<Option<(u64, Vec<String>)>, ApiError> {
+  if !ctx.delta_eligible(item.size) {
+    return Ok(None);
+  }
+  // the server can't truncate a file, the new blocks of a file that grew are written past its end
+  if item.remote_size.is_some_and(|remote_size| item.size < remote_size) {
+    return Ok(None);
+  }
+  // the remote copy must still be the one this client uploaded last time
+  let uploaded_size = ctx.state.files.get(&item.virtual_path).and_then(|record| record.remote_size);
+  if item.remote_size.is_none() || uploaded_size != item.remote_size {
+    return Ok(None);
+  }
+  let old_hashes = match ctx.state.chunks.get(&item.virtual_path) {
+    Some(hashes) => hashes.clone(),
+    None => return Ok(None),
+  };
+
+  let new_hashes = file_conn::chunk_hashes(local_path, CHUNK_SIZE)?;
+  let changed = changed_chunks(&old_hashes, &new_hashes);
+  crate::progress!("updating  {} ({} of {} chunks changed)", item.virtual_path, changed.len(), new_hashes.len());
+
+  let mut file = File::open(local_path)?;
+  let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+  for index in changed {
+    let position = index as u64 * CHUNK_SIZE;
+    file.seek(SeekFrom::Start(position))?;
+    let filled = file_conn::read_chunk(&mut file, &mut buffer)?;
+    ctx.remote.write_chunk(remote_path, position, buffer[..filled].to_vec()).await?;
+  }
+
+  verify_upload(ctx.remote, local_path, remote_path, item.size, None).await?;
+  Ok(Some((item.size, new_hashes)))
+}
+
+/// Downloads a remote file, decrypting and decompressing it when needed. Returns the remote size.
+pub async fn receive_file(ctx: &mut SyncContext<'_>, remote_path: &str, local_path: &str) -> Result<u64, ApiError> {
+  if ctx.cipher.is_none() && ctx.dir.compression.is_none() {
+    return download_file(ctx.remote, remote_path, local_path).await;
+  }
+
+  let download_path = sync_state::temp_file("download")?;
+  let result = receive_transformed(ctx, remote_path, &download_path, local_path).await;
+  let _ = file_conn::remove_file(&download_path);
+  result
+}
+
+async fn receive_transformed(ctx: &mut SyncContext<'_>, remote_path: &str, download_path: &str, local_path: &str) -> Result<u64, ApiError> {
+  let received = download_file(ctx.remote, remote_path, download_path).await?;
+
+  let mut stream: Stream = Box::new(File::open(download_path)?);
+  if let Some(cipher) = ctx.cipher.as_mut() {
+    stream = Box::new(cipher.decrypt_reader(stream)?);
+  }
+  if ctx.dir.compression.is_some() {
+    stream = compression::decompress_reader(stream)?;
+  }
+
+  let part_path = utils::part_path(local_path);
+  let written = File::create(&part_path).and_then(|mut target| io::copy(&mut stream, &mut target));
+  if let Err(err) = written {
+    let _ = file_conn::remove_file(&part_path);
+    return Err(err.into());
+  }
+
+  file_conn::rename_file(&part_path, local_path)?;
+  Ok(received)
+}
+
 #[async_recursion]
-async fn send_files(dirs: &objects::Dirsync, api_client: &api_conn::ApiClient, virtual_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-  let local_path = &dirs.local_path;
-  let remote_path = &dirs.remote_path;
-
-  let virtual_local_path: String;
-  let virtual_remote_path: String;
-
-  if virtual_path.is_empty() {
-    virtual_local_path = local_path.clone();
-    virtual_remote_path = remote_path.clone();    
-  } else {
-    virtual_local_path = utils::create_path(local_path, virtual_path);
-    virtual_remote_path = utils::create_path(remote_path, virtual_path);
-  }
-  
-  let files_local_list = file_conn::file_list(&virtual_local_path);
-  let files_remote_list = api_client.get_files_list(&virtual_remote_path).await.unwrap();
-
-  for file in files_local_list {
-    let virtual_path_file = &utils::create_path(virtual_path, &file);
-    let local_path_file = &utils::create_path(&virtual_local_path, &file);
-    let remote_path_file = &utils::create_path(&virtual_remote_path, &file);
-
-    let is_dir = file_conn::is_dir(local_path_file).unwrap();
-    let file_size = file_conn::get_file_size(local_path_file).unwrap();
-    let exists_file_remote = utils::exists_file_remote(&files_remote_list, &file);
-
-    if is_dir {
-      if !exists_file_remote {
-        api_client.create_folder(&remote_path_file).await.unwrap();
-        println!("dir created {}",utils::create_path(virtual_path, &file));
+async fn plan_dir(ctx: &mut SyncContext<'_>, virtual_path: &str, local_exists: bool, remote_exists: bool, plan: &mut Vec<PlanItem>) -> Result<(), ApiError> {
+  let sync_mode = ctx.dir.sync_mode;
+
+  let local_names = if local_exists { file_conn::file_list(&ctx.local_path(virtual_path)) } else { Vec::new() };
+  let remote_list = if remote_exists { ctx.remote_list(virtual_path).await? } else { Vec::new() };
+
+  let mut names: BTreeSet<String> = local_names.into_iter().collect();
+  names.extend(remote_list.iter().map(|file| file.name.clone()));
+
+  for name in names.iter().filter(|name| !utils::is_reserved_name(name)) {
+    let item_path = utils::create_path(virtual_path, name);
+    let local_stat = if local_exists { file_conn::get_file_stat(&ctx.local_path(&item_path)).ok() } else { None };
+    let remote_file = remote_list.iter().find(|file| &file.name == name);
+
+    // links and other special entries of the server are neither downloaded nor replaced
+    if let Some(objects::File { r#type: EntryType::Other(entry_type), .. }) = remote_file {
+      crate::progress!("skipped {}, {} entries are not synced", item_path, entry_type);
+      continue;
+    }
+
+    match (local_stat, remote_file) {
+      (Some(local), Some(remote_f)) => {
+        let remote_is_dir = remote_f.r#type == EntryType::Folder;
+        if local.is_dir && remote_is_dir {
+          plan_dir(ctx, &item_path, true, true, plan).await?;
+        } else if local.is_dir != remote_is_dir {
+          plan.push(PlanItem { action: Action::Conflict, virtual_path: item_path, size: local.size, remote_size: remote_f.size });
+        } else {
+          let local_hash = ctx.local_hash(&item_path, &local)?;
+          let record = ctx.state.files.get(&item_path);
+          let action = match compare::decide(ctx.dir, &local, local_hash.as_deref(), remote_f, record) {
+            Decision::Same => Action::Unchanged,
+            Decision::Upload => Action::Upload,
+            Decision::Download => Action::Download,
+            Decision::Conflict => Action::Conflict,
+          };
+          let size = if action == Action::Download { remote_f.size.unwrap_or(0) } else { local.size };
+          plan.push(PlanItem { action, virtual_path: item_path, size, remote_size: remote_f.size });
+        }
+      },
+      (Some(local), None) => {
+        if !compare::sends(sync_mode) {
+          if ctx.dir.mirror && compare::receives(sync_mode) {
+            plan.push(PlanItem { action: Action::DeleteLocal, virtual_path: item_path, size: local.size, remote_size: None });
+          }
+          continue;
+        }
+        // names too long once encrypted are refused here, before anything is uploaded
+        if let Err(err) = ctx.remote_path(&item_path) {
+          eprintln!("Error: {} ({})", err, item_path);
+          ctx.rejected += 1;
+          continue;
+        }
+        if local.is_dir {
+          plan.push(PlanItem { action: Action::CreateRemoteDir, virtual_path: item_path.clone(), size: 0, remote_size: None });
+          plan_dir(ctx, &item_path, true, false, plan).await?;
+        } else {
+          plan.push(PlanItem { action: Action::Upload, virtual_path: item_path, size: local.size, remote_size: None });
+        }
+      },
+      (None, Some(remote_f)) => {
+        if !compare::receives(sync_mode) {
+          // only what was synced from here was deleted locally, the rest was put there by someone else
+          if ctx.dir.mirror && compare::sends(sync_mode) && ctx.was_synced(&item_path) {
+            plan.push(PlanItem { action: Action::DeleteRemote, virtual_path: item_path, size: remote_f.size.unwrap_or(0), remote_size: remote_f.size });
+          } else if ctx.dir.mirror && compare::sends(sync_mode) {
+            crate::detail!("kept      {} (never synced from here)", item_path);
+          }
+          continue;
+        }
+        if remote_f.r#type == EntryType::Folder {
+          plan.push(PlanItem { action: Action::CreateLocalDir, virtual_path: item_path.clone(), size: 0, remote_size: None });
+          plan_dir(ctx, &item_path, false, true, plan).await?;
+        } else {
+          plan.push(PlanItem { action: Action::Download, virtual_path: item_path, size: remote_f.size.unwrap_or(0), remote_size: remote_f.size });
+        }
+      },
+      (None, None) => {},
+    }
+  }
+
+  Ok(())
+}
+
+/// Walks both sides of `ctx.dir` and lists what a sync would do, without touching anything.
+pub async fn plan_sync(ctx: &mut SyncContext<'_>) -> Result<Vec<PlanItem>, ApiError> {
+  let mut plan: Vec<PlanItem> = Vec::new();
+  let local_exists = file_conn::file_exists(&ctx.dir.local_path);
+  // an unmounted or moved folder looks like every file was deleted
+  if !local_exists && ctx.dir.mirror && compare::sends(ctx.dir.sync_mode) {
+    return Err(ApiError::InvalidPath { path: ctx.dir.local_path.clone(), reason: String::from("the local folder is missing, mirror would delete the remote files") });
+  }
+  plan_dir(ctx, "", local_exists, true, &mut plan).await?;
+  Ok(plan)
+}
+
+async fn execute_item(ctx: &mut SyncContext<'_>, item: &PlanItem) -> Result<(), ApiError> {
+  let local_path = ctx.local_path(&item.virtual_path);
+  let remote_path = ctx.remote_path(&item.virtual_path)?;
+
+  match item.action {
+    Action::CreateLocalDir => {
+      file_conn::create_dir(&local_path);
+      crate::progress!("dir created {}", item.virtual_path);
+    },
+    Action::CreateRemoteDir => {
+      ctx.remote.create_folder(&remote_path).await?;
+      crate::progress!("dir created {}", item.virtual_path);
+    },
+    Action::Download => {
+      crate::detail!("downloading {}", item.virtual_path);
+      let new_path = utils::temp_path(&local_path, "new");
+      let received = receive_file(ctx, &remote_path, &new_path).await?;
+      if file_conn::file_exists(&local_path) {
+        versions::archive(ctx.dir, &item.virtual_path)?;
+      }
+      file_conn::rename_file(&new_path, &local_path)?;
+      ctx.record(&item.virtual_path, Some(received))?;
+      crate::progress!("downloaded  {}", item.virtual_path);
+    },
+    Action::Upload => {
+      crate::detail!("uploading {}", item.virtual_path);
+      let (remote_size, hashes) = match delta_upload(ctx, &local_path, &remote_path, item).await {
+        Ok(Some((remote_size, hashes))) => (remote_size, Some(hashes)),
+        Ok(None) => (send_file(ctx, &local_path, &remote_path, item.size, &item.virtual_path).await?, None),
+        Err(err) => {
+          crate::progress!("retrying  {} in full ({})", item.virtual_path, err);
+          (send_file(ctx, &local_path, &remote_path, item.size, &item.virtual_path).await?, None)
+        },
+      };
+      ctx.record(&item.virtual_path, Some(remote_size))?;
+      ctx.record_chunks(&item.virtual_path, item.size, hashes)?;
+      crate::progress!("uploaded  {}", item.virtual_path);
+    },
+    Action::DeleteLocal => {
+      versions::archive(ctx.dir, &item.virtual_path)?;
+      // archived files are already gone, only their empty folders are left
+      if file_conn::file_exists(&local_path) {
+        file_conn::remove_path(&local_path)?;
       }
-      let _ = send_files(dirs, api_client, &file).await.unwrap();
-    } else {
-      if !exists_file_remote {
-        println!("uploading {}",virtual_path_file);
-        let _ = upload_file(api_client, &local_path_file, &remote_path_file, file_size, virtual_path_file).await;
-        println!("uploaded  {}",virtual_path_file);
+      ctx.forget(&item.virtual_path);
+      crate::progress!("deleted   {}", item.virtual_path);
+    },
+    Action::DeleteRemote => {
+      if ctx.dir.trash {
+        let destination = trash::move_to_trash(ctx.dir, ctx.remote, &remote_path).await?;
+        crate::progress!("trashed   {} ({})", item.virtual_path, destination);
       } else {
-        println!("found     {}",virtual_path_file);
+        ctx.remote.delete_file(&remote_path).await?;
+        crate::progress!("deleted   {} (remote)", item.virtual_path);
       }
-    }
+      ctx.forget(&item.virtual_path);
+    },
+    Action::Unchanged => {
+      ctx.record(&item.virtual_path, item.remote_size)?;
+      crate::detail!("found     {}", item.virtual_path);
+    },
+    Action::Conflict => {
+      crate::progress!("conflict  {} (changed on both sides, skipped)", item.virtual_path);
+    },
   }
-  
+
   Ok(())
 }
 
-pub async fn sync_files(dir: &objects::Dirsync, api_client: &api_conn::ApiClient) -> Result<String, Box<dyn std::error::Error>> {
-  let sync_mode = &dir.sync_mode;
-  let remote_path = &dir.remote_path;
+/// What a plan item would do, `None` for the ones that change nothing.
+fn dry_run_action(ctx: &SyncContext<'_>, item: &PlanItem) -> Option<&'static str> {
+  match item.action {
+    Action::CreateLocalDir => Some("create local dir"),
+    Action::CreateRemoteDir => Some("create remote dir"),
+    Action::Download => Some("download"),
+    Action::Upload => Some("upload"),
+    Action::DeleteLocal => Some("delete local"),
+    Action::DeleteRemote if ctx.dir.trash => Some("trash"),
+    Action::DeleteRemote => Some("delete remote"),
+    Action::Conflict => Some("skip conflict"),
+    Action::Unchanged => None,
+  }
+}
+
+fn report_plan(ctx: &SyncContext<'_>, plan: &[PlanItem]) -> String {
+  let mut changes: u32 = 0;
+  for item in plan.iter() {
+    match dry_run_action(ctx, item) {
+      Some(action) => {
+        crate::progress!("would {} {}", action, item.virtual_path);
+        changes += 1;
+      },
+      None => crate::detail!("found     {}", item.virtual_path),
+    }
+  }
+  if ctx.rejected > 0 {
+    return format!("dry run: {} changes planned in {}, {} entries can't be uploaded", changes, ctx.dir.local_path, ctx.rejected);
+  }
+  format!("dry run: {} changes planned in {}", changes, ctx.dir.local_path)
+}
 
-  let file_properties = api_client.get_file_properties(&remote_path).await.unwrap();
+pub async fn sync_files(dir: &objects::Dirsync, remote_client: &remote::Remote, dry_run: bool) -> Result<String, Box<dyn std::error::Error>> {
+  if dir.sync_mode == SyncMode::Backup && dry_run {
+    return backup::backup_dry_run(dir, remote_client).await;
+  }
+  if dir.sync_mode == SyncMode::Backup && !remote_client.exists_file(&dir.remote_path).await?.exists {
+    remote_client.create_folder(&dir.remote_path).await?;
+  }
+  let file_properties = remote_client.get_file_properties(&dir.remote_path).await?;
 
-  if file_properties.r#type == "file" {
-    return Ok(String::from("files cannot be synced"));
+  match file_properties.r#type {
+    EntryType::Folder => {},
+    EntryType::File => return Ok(String::from("files cannot be synced")),
+    EntryType::Other(name) => return Ok(format!("{} entries cannot be synced", name)),
+  }
+  if dir.sync_mode == SyncMode::Backup {
+    return backup::backup_files(dir, remote_client).await;
   }
 
-  if sync_mode == "get" || sync_mode == "bidirectional" {
-    let _ = get_files(dir, api_client, "").await;
+  if compare::receives(dir.sync_mode) && !file_conn::file_exists(&dir.local_path) && !dry_run {
+    file_conn::create_dir(&dir.local_path);
+    crate::progress!("dir created {}", dir.local_path);
   }
-  if sync_mode == "send" || sync_mode == "bidirectional" {
-    let _ = send_files(dir, api_client, "").await;
+
+  let mut ctx = SyncContext::new(dir, remote_client)?;
+  ctx.dry_run = dry_run;
+  let plan = plan_sync(&mut ctx).await?;
+  if ctx.dry_run {
+    return Ok(report_plan(&ctx, &plan));
   }
 
-  Ok(String::from("sync finished"))  
+  let mut failed: u32 = ctx.rejected;
+  for item in plan.iter() {
+    if let Err(err) = execute_item(&mut ctx, item).await {
+      eprintln!("Error: {} ({})", err, item.virtual_path);
+      failed += 1;
+    }
+  }
+
+  if failed == 0 {
+    ctx.state.last_sync = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+    ctx.hashes.prune();
+  }
+  ctx.save()?;
+
+  if failed > 0 {
+    return Ok(format!("sync finished with {} errors", failed));
+  }
+  Ok(String::from("sync finished"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  fn hashes_of(content: &[u8], chunk_size: u64) -> Vec<String> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = format!("kcloud-test-chunks-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    fs::write(&path, content).unwrap();
+    let hashes = file_conn::chunk_hashes(&path.display().to_string(), chunk_size).unwrap();
+    let _ = fs::remove_file(&path);
+    hashes
+  }
+
+  #[test]
+  fn unchanged_file_has_no_changed_chunks() {
+    let hashes = hashes_of(b"aaaabbbbcccc", 4);
+    assert!(changed_chunks(&hashes, &hashes).is_empty());
+  }
+
+  #[test]
+  fn only_the_edited_chunk_is_changed() {
+    let old = hashes_of(b"aaaabbbbcccc", 4);
+    let new = hashes_of(b"aaaabxbbcccc", 4);
+    assert_eq!(changed_chunks(&old, &new), [1]);
+  }
+
+  #[test]
+  fn grown_file_changes_its_last_chunk_and_the_new_ones() {
+    let old = hashes_of(b"aaaabb", 4);
+    let new = hashes_of(b"aaaabbbbcccc", 4);
+    assert_eq!(changed_chunks(&old, &new), [1, 2]);
+  }
+
+  #[test]
+  fn chunks_that_moved_are_changed() {
+    // inserting a byte shifts every following chunk, delta uploads don't look for moved blocks
+    let old = hashes_of(b"aaaabbbbcccc", 4);
+    let new = hashes_of(b"aaaaxbbbbcccc", 4);
+    assert_eq!(changed_chunks(&old, &new), [1, 2, 3]);
+  }
 }
@@ -4,8 +4,11 @@
  * MIT Licensed
  */
 use std::{fs::read_dir, fs::File, fs::metadata};
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
-use std::io;
+use std::io::{self, Read};
+use sha2::{Digest, Sha256};
+use crate::core::objects::{FileStat, HashAlgorithm};
 // use std::io::copy;
 // use filesize::PathExt;
 
@@ -52,6 +55,82 @@ pub fn get_file_size(path: &str) -> io::Result<u64> {
   Ok(realsize)
 }
 
+pub fn get_file_stat(path: &str) -> io::Result<FileStat> {
+  let metadata = metadata(path)?;
+  Ok(FileStat {
+    is_dir: metadata.is_dir(),
+    size: metadata.len(),
+    mtime: metadata.mtime(),
+    mtime_nsec: metadata.mtime_nsec(),
+    dev: metadata.dev(),
+    ino: metadata.ino(),
+  })
+}
+
+pub fn remove_file(path: &str) -> io::Result<()> {
+  std::fs::remove_file(path)
+}
+
+/// Removes a file or a whole folder.
+pub fn remove_path(path: &str) -> io::Result<()> {
+  match metadata(path)?.is_dir() {
+    true => std::fs::remove_dir_all(path),
+    false => std::fs::remove_file(path),
+  }
+}
+
+pub fn hash_file(path: &str) -> io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  io::copy(&mut file, &mut hasher)?;
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn hash_file_with(path: &str, algorithm: HashAlgorithm) -> io::Result<String> {
+  match algorithm {
+    HashAlgorithm::Sha256 => hash_file(path),
+    HashAlgorithm::Blake3 => {
+      let mut file = File::open(path)?;
+      let mut hasher = blake3::Hasher::new();
+      io::copy(&mut file, &mut hasher)?;
+      Ok(hasher.finalize().to_hex().to_string())
+    },
+  }
+}
+
+/// Hashes `path` in blocks of `chunk_size` bytes.
+pub fn chunk_hashes(path: &str, chunk_size: u64) -> io::Result<Vec<String>> {
+  let mut file = File::open(path)?;
+  let mut hashes: Vec<String> = Vec::new();
+  let mut buffer = vec![0u8; chunk_size as usize];
+
+  loop {
+    let filled = read_chunk(&mut file, &mut buffer)?;
+    if filled == 0 {
+      break;
+    }
+    hashes.push(blake3::hash(&buffer[..filled]).to_hex().to_string());
+  }
+  Ok(hashes)
+}
+
+/// Fills `buffer` unless the end of the file is reached first, returns the bytes read.
+pub fn read_chunk<R: Read + ?Sized>(source: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    let bytes_read = source.read(&mut buffer[filled..])?;
+    if bytes_read == 0 {
+      break;
+    }
+    filled += bytes_read;
+  }
+  Ok(filled)
+}
+
+pub fn rename_file(from: &str, to: &str) -> io::Result<()> {
+  std::fs::rename(from, to)
+}
+
 // pub fn save_file(path: &str, content: &Cursor<Vec<u8>>) {
 //   let mut file = std::fs::File::create(path).unwrap();
 //   copy(&mut content.clone(), &mut file).unwrap();
@@ -0,0 +1,135 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::path::Path;
+use crate::core::api_conn::ApiError;
+use crate::core::engine::{self, SyncContext};
+use crate::core::objects::EntryType;
+use crate::core::{file_conn, trash, utils};
+
+fn invalid_path(path: &str, reason: &str) -> ApiError {
+  ApiError::InvalidPath { path: path.to_string(), reason: reason.to_string() }
+}
+
+fn last_segment(path: &str) -> &str {
+  path.trim_end_matches('/').rsplit('/').next().unwrap_or(path)
+}
+
+async fn is_remote_folder(ctx: &mut SyncContext<'_>, virtual_path: &str) -> Result<bool, ApiError> {
+  let remote_path = ctx.remote_path(virtual_path)?;
+  if !ctx.remote.exists_file(&remote_path).await?.exists {
+    return Ok(false);
+  }
+  Ok(ctx.remote.get_file_properties(&remote_path).await?.r#type == EntryType::Folder)
+}
+
+/// `destination` itself, or the entry named after `source` inside it when it is an existing folder.
+async fn remote_target(ctx: &mut SyncContext<'_>, source: &str, destination: &str) -> Result<String, ApiError> {
+  match is_remote_folder(ctx, destination).await? {
+    true => Ok(utils::create_path(destination, last_segment(source))),
+    false => Ok(destination.to_string()),
+  }
+}
+
+/// Uploads one local file, encrypting and compressing it when the dir asks for it.
+pub async fn put(ctx: &mut SyncContext<'_>, local_path: &str, virtual_path: &str) -> Result<String, ApiError> {
+  let stat = file_conn::get_file_stat(local_path)?;
+  if stat.is_dir {
+    return Err(invalid_path(local_path, "is a folder, use a send dir to upload folders"));
+  }
+  let target = remote_target(ctx, local_path, virtual_path).await?;
+  let remote_path = ctx.remote_path(&target)?;
+
+  if ctx.dry_run {
+    return Ok(format!("would upload {} -> {}", local_path, target));
+  }
+  engine::send_file(ctx, local_path, &remote_path, stat.size, &target).await?;
+  Ok(format!("uploaded  {} -> {}", local_path, target))
+}
+
+/// Downloads one remote file, into `local_path` when it is an existing folder.
+pub async fn get(ctx: &mut SyncContext<'_>, virtual_path: &str, local_path: &str) -> Result<String, ApiError> {
+  let remote_path = ctx.remote_path(virtual_path)?;
+  if ctx.remote.get_file_properties(&remote_path).await?.r#type == EntryType::Folder {
+    return Err(invalid_path(virtual_path, "is a folder, use restore to download folders"));
+  }
+  let target = match file_conn::is_dir(local_path) {
+    Ok(true) => utils::create_path(local_path, last_segment(virtual_path)),
+    _ => local_path.to_string(),
+  };
+  if let Some(parent) = Path::new(&target).parent() && !parent.as_os_str().is_empty() && !parent.exists() {
+    return Err(invalid_path(&target, "parent folder does not exist"));
+  }
+
+  if ctx.dry_run {
+    return Ok(format!("would download {} -> {}", virtual_path, target));
+  }
+  engine::receive_file(ctx, &remote_path, &target).await?;
+  Ok(format!("downloaded {} -> {}", virtual_path, target))
+}
+
+pub async fn mkdir(ctx: &mut SyncContext<'_>, virtual_path: &str, parents: bool) -> Result<String, ApiError> {
+  let remote_path = ctx.remote_path(virtual_path)?;
+  if ctx.dry_run {
+    return Ok(format!("would create dir {}", virtual_path));
+  }
+  match parents {
+    true => ctx.remote.create_folder_all(&remote_path).await?,
+    false => {
+      ctx.remote.create_folder(&remote_path).await?;
+    },
+  }
+  Ok(format!("dir created {}", virtual_path))
+}
+
+/// Moves a remote file to the trash, or deletes it when the dir has no trash. Folders need `recursive`.
+pub async fn rm(ctx: &mut SyncContext<'_>, virtual_path: &str, recursive: bool) -> Result<String, ApiError> {
+  if virtual_path.trim_matches('/').is_empty() {
+    return Err(invalid_path(virtual_path, "refusing to delete the root folder"));
+  }
+  let remote_path = ctx.remote_path(virtual_path)?;
+  if ctx.remote.get_file_properties(&remote_path).await?.r#type == EntryType::Folder && !recursive {
+    return Err(invalid_path(virtual_path, "is a folder, use -r to delete it with its content"));
+  }
+
+  // what is already in the trash is deleted for good
+  let trashed = ctx.dir.trash && !virtual_path.split('/').any(utils::is_reserved_name);
+  match (trashed, ctx.dry_run) {
+    (true, true) => Ok(format!("would trash {}", virtual_path)),
+    (true, false) => {
+      let destination = trash::move_to_trash(ctx.dir, ctx.remote, &remote_path).await?;
+      Ok(format!("trashed   {} ({})", virtual_path, destination))
+    },
+    (false, true) => Ok(format!("would delete {}", virtual_path)),
+    (false, false) => {
+      ctx.remote.delete_file(&remote_path).await?;
+      Ok(format!("deleted   {}", virtual_path))
+    },
+  }
+}
+
+pub async fn mv(ctx: &mut SyncContext<'_>, source: &str, destination: &str) -> Result<String, ApiError> {
+  let target = remote_target(ctx, source, destination).await?;
+  let from = ctx.remote_path(source)?;
+  let to = ctx.remote_path(&target)?;
+
+  if ctx.dry_run {
+    return Ok(format!("would move {} -> {}", source, target));
+  }
+  ctx.remote.move_file(&from, &to).await?;
+  Ok(format!("moved     {} -> {}", source, target))
+}
+
+pub async fn cp(ctx: &mut SyncContext<'_>, source: &str, destination: &str) -> Result<String, ApiError> {
+  let target = remote_target(ctx, source, destination).await?;
+  let from = ctx.remote_path(source)?;
+  let to = ctx.remote_path(&target)?;
+
+  if ctx.dry_run {
+    return Ok(format!("would copy {} -> {}", source, target));
+  }
+  ctx.remote.copy_file(&from, &to).await?;
+  Ok(format!("copied    {} -> {}", source, target))
+}
@@ -0,0 +1,233 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::env;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use serde_json::{json, Map, Value};
+use crate::core::objects::{EntryType, Server, SyncMode};
+use crate::core::remote::Remote;
+use crate::core::{commands, config_file, output, utils};
+
+pub struct InitOptions {
+  pub url: Option<String>,
+  pub api_key: Option<String>,
+  pub api_key_env: Option<String>,
+  /// Local folder, remote folder and sync mode of every dir given on the command line.
+  pub dirs: Vec<(String, String, String)>,
+  /// Connects to the server to check the url and the api key.
+  pub verify: bool,
+  pub interactive: bool,
+}
+
+/// Asks `question` on the terminal, `default` is taken for an empty answer.
+fn ask(question: &str, default: &str) -> Result<String, String> {
+  match default.is_empty() {
+    true => print!("{}: ", question),
+    false => print!("{} [{}]: ", question, default),
+  }
+  io::stdout().flush().map_err(|err| err.to_string())?;
+
+  let mut answer = String::new();
+  match io::stdin().lock().read_line(&mut answer) {
+    Ok(0) => Err(String::from("no more answers to read")),
+    Ok(_) if answer.trim().is_empty() => Ok(default.to_string()),
+    Ok(_) => Ok(answer.trim().to_string()),
+    Err(err) => Err(err.to_string()),
+  }
+}
+
+fn set_echo(on: bool) {
+  let _ = Command::new("stty").arg(if on { "echo" } else { "-echo" }).stdin(Stdio::inherit()).status();
+}
+
+/// Like `ask`, without showing what is typed.
+fn ask_secret(question: &str) -> Result<String, String> {
+  let terminal = io::stdin().is_terminal();
+  if terminal {
+    set_echo(false);
+  }
+  let answer = ask(question, "");
+  if terminal {
+    set_echo(true);
+    println!();
+  }
+  answer
+}
+
+fn parse_mode(mode: &str) -> Result<SyncMode, String> {
+  serde_json::from_value(Value::from(mode)).map_err(|_| format!("unknown sync mode {}, use send, get, bidirectional or backup", mode))
+}
+
+/// Server described by the options, asking for what is missing until it can be connected.
+async fn ask_server(options: &InitOptions) -> Result<(Server, Option<Remote>), String> {
+  let mut url = options.url.clone();
+  let mut api_key = options.api_key.clone();
+  let mut last_url = String::new();
+
+  loop {
+    let base_url = match (url.take(), options.interactive) {
+      (Some(url), _) => url,
+      (None, true) => ask("Server url", &last_url)?,
+      (None, false) => return Err(String::from("--url is needed with --non-interactive")),
+    };
+    if base_url.is_empty() {
+      continue;
+    }
+    last_url = base_url.clone();
+
+    let mut server = Server { base_url, ..Default::default() };
+    if let Some(variable) = &options.api_key_env {
+      server.api_key_env = variable.clone();
+    } else if !utils::is_local_backend(&server.base_url, &server.backend) {
+      server.api_key = match (api_key.take(), options.interactive) {
+        (Some(key), _) => key,
+        (None, true) => ask_secret("Api key")?,
+        (None, false) => return Err(String::from("--api-key or --api-key-env is needed with --non-interactive")),
+      };
+    }
+
+    if !options.verify {
+      return Ok((server, None));
+    }
+    match commands::connect("", &server).await {
+      Ok(remote) => {
+        println!("connected to {}", server.base_url);
+        return Ok((server, Some(remote)));
+      },
+      Err(err) if options.interactive => output::error(err),
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+/// Lets the user walk the remote folders and pick one, or create a new one.
+async fn browse(remote: &Remote, dry_run: bool) -> Result<String, String> {
+  let mut path = String::new();
+  loop {
+    let mut folders: Vec<String> = remote.get_files_list(&path).await.map_err(|err| err.to_string())?.list
+      .into_iter()
+      .filter(|file| file.r#type == EntryType::Folder && !utils::is_reserved_name(&file.name))
+      .map(|file| file.name)
+      .collect();
+    folders.sort();
+
+    println!("\n/{}", path);
+    for (i, folder) in folders.iter().enumerate() {
+      println!("  {}. {}/", i + 1, folder);
+    }
+    let answer = ask("Number to open, .. to go up, a new name to create it, empty to pick this folder", "")?;
+
+    match answer.as_str() {
+      "" if path.is_empty() => println!("pick a folder below the root"),
+      "" => return Ok(path),
+      ".." => path = path.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default(),
+      _ => match answer.parse::<usize>() {
+        Ok(number) if (1..=folders.len()).contains(&number) => path = utils::create_path(&path, &folders[number - 1]),
+        Ok(_) => println!("there is no folder {}", answer),
+        Err(_) => {
+          let created = utils::create_path(&path, answer.trim_matches('/'));
+          match dry_run {
+            true => println!("would create {}", created),
+            false => {
+              remote.create_folder_all(&created).await.map_err(|err| err.to_string())?;
+              println!("created {}", created);
+            },
+          }
+          return Ok(created);
+        },
+      },
+    }
+  }
+}
+
+/// Asks for the dirs to sync, at least one.
+async fn ask_dirs(remote: Option<&Remote>, dry_run: bool) -> Result<Vec<Value>, String> {
+  let mut dirs: Vec<Value> = Vec::new();
+  loop {
+    let remote_path = match remote {
+      Some(remote) => browse(remote, dry_run).await?,
+      None => ask("Remote folder", "")?,
+    };
+    let folder_name = remote_path.rsplit('/').next().unwrap_or(&remote_path);
+    let default_local = env::current_dir().map(|dir| dir.join(folder_name).display().to_string()).unwrap_or_default();
+    let local_path = ask("Local folder", &default_local)?;
+
+    let sync_mode = loop {
+      match parse_mode(&ask("Sync mode: send, get, bidirectional or backup", "bidirectional")?) {
+        Ok(mode) => break mode,
+        Err(err) => println!("{}", err),
+      }
+    };
+
+    let mut dir = json!({ "remote_path": remote_path, "local_path": local_path, "sync_mode": sync_mode });
+    let name = ask("Name to select it on the command line, empty for none", "")?;
+    if !name.is_empty() {
+      dir["name"] = Value::from(name);
+    }
+    dirs.push(dir);
+
+    if !ask("Add another folder? (y/n)", "n")?.to_lowercase().starts_with('y') {
+      return Ok(dirs);
+    }
+  }
+}
+
+/// Writes `content` where only the owner can read it, the api key may be in there.
+fn write_private(path: &str, content: &str) -> io::Result<()> {
+  if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    fs::create_dir_all(parent)?;
+  }
+  let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+  // the mode is only used for new files
+  file.set_permissions(Permissions::from_mode(0o600))?;
+  file.write_all(content.as_bytes())
+}
+
+/// Writes a new config file at `path` in `format`, checked like it would be when loaded.
+pub async fn init(path: &str, format: &str, options: &InitOptions, dry_run: bool) -> Result<String, String> {
+  let (server, remote) = ask_server(options).await?;
+
+  let dirs = match (options.dirs.is_empty(), options.interactive) {
+    (false, _) => options.dirs.iter()
+      .map(|(local_path, remote_path, sync_mode)| Ok(json!({ "remote_path": remote_path, "local_path": local_path, "sync_mode": parse_mode(sync_mode)? })))
+      .collect::<Result<Vec<Value>, String>>()?,
+    (true, true) => ask_dirs(remote.as_ref(), dry_run).await?,
+    (true, false) => return Err(String::from("--add-dir is needed with --non-interactive")),
+  };
+
+  let mut config = Map::new();
+  config.insert(String::from("base_url"), Value::from(server.base_url));
+  if !server.api_key.is_empty() {
+    config.insert(String::from("api_key"), Value::from(server.api_key));
+  }
+  if !server.api_key_env.is_empty() {
+    config.insert(String::from("api_key_env"), Value::from(server.api_key_env));
+  }
+  config.insert(String::from("dirs"), Value::from(dirs));
+  let mut config = Value::from(config);
+  // what was typed is meant as it is, not as variables to expand when the config loads
+  config_file::escape_value(&mut config, "");
+
+  let problems = config_file::check_value(&config).map_err(|err| err.to_string())?;
+  let (warnings, errors): (Vec<_>, Vec<_>) = problems.into_iter().partition(|problem| problem.warning);
+  for warning in warnings.iter() {
+    output::warning(warning);
+  }
+  if !errors.is_empty() {
+    return Err(config_file::ConfigError::Invalid(errors).to_string());
+  }
+
+  let content = config_file::render(config, format).map_err(|err| err.to_string())?;
+  if dry_run {
+    print!("{}", content);
+    return Ok(format!("dry run: would write {}", path));
+  }
+  write_private(path, &content).map_err(|err| format!("{} ({})", err, path))?;
+  Ok(format!("{} written", path))
+}
@@ -0,0 +1,191 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use async_recursion::async_recursion;
+use url::Url;
+use crate::core::api_conn::{ApiError, CHUNK_SIZE};
+use crate::core::{objects, utils};
+
+fn extension_of(path: &Path, is_dir: bool) -> Option<String> {
+  match is_dir {
+    true => None,
+    false => path.extension().map(|extension| extension.to_string_lossy().to_string()),
+  }
+}
+
+#[async_recursion]
+async fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+  if !tokio::fs::metadata(from).await?.is_dir() {
+    tokio::fs::copy(from, to).await?;
+    return Ok(());
+  }
+  tokio::fs::create_dir_all(to).await?;
+  let mut entries = tokio::fs::read_dir(from).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    copy_recursive(&entry.path(), &to.join(entry.file_name())).await?;
+  }
+  Ok(())
+}
+
+/// Remote backed by another local directory (external disk, NAS mount...).
+pub struct LocalClient {
+  root: PathBuf,
+}
+
+impl LocalClient {
+  pub fn new(base_url: &str) -> Result<Self, ApiError> {
+    let root = match base_url.strip_prefix("file://") {
+      Some(_) => Url::parse(base_url)?
+        .to_file_path()
+        .map_err(|_| url::ParseError::RelativeUrlWithoutBase)?,
+      None => PathBuf::from(base_url),
+    };
+
+    Ok(Self { root })
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  fn resolve(&self, path: &str) -> PathBuf {
+    self.root.join(path.trim_start_matches('/'))
+  }
+
+  pub async fn get_file_properties(&self, path: &str) -> Result<objects::FileProperties, ApiError> {
+    let target = self.resolve(path);
+    let metadata = tokio::fs::metadata(&target).await?;
+    let r#type = if metadata.is_dir() { objects::EntryType::Folder } else { objects::EntryType::File };
+
+    Ok(objects::FileProperties {
+      name: target.file_name().map(|name| name.to_string_lossy().to_string()),
+      r#type,
+      size: Some(metadata.len()),
+      sha256: None,
+      extension: extension_of(&target, metadata.is_dir()),
+      mime_type: None,
+      modified: Some(serde_json::Value::from(metadata.mtime())),
+    })
+  }
+
+  pub async fn get_files_list(&self, path: &str) -> Result<objects::FileList, ApiError> {
+    let mut entries = tokio::fs::read_dir(self.resolve(path)).await?;
+    let mut list: Vec<objects::File> = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+      let metadata = entry.metadata().await?;
+      let r#type = if metadata.is_dir() { objects::EntryType::Folder } else { objects::EntryType::File };
+      list.push(objects::File {
+        name: entry.file_name().to_string_lossy().to_string(),
+        r#type,
+        size: Some(metadata.len()),
+        sha256: None,
+        extension: extension_of(&entry.path(), metadata.is_dir()),
+        mime_type: None,
+        modified: Some(serde_json::Value::from(metadata.mtime())),
+      });
+    }
+
+    Ok(objects::FileList { list })
+  }
+
+  pub async fn get_file(&self, path: &str, mut file: &fs::File) -> Result<u64, ApiError> {
+    let source = tokio::fs::File::open(self.resolve(path)).await?;
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE as usize, source);
+    let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+    let mut position: u64 = 0;
+
+    loop {
+      let bytes_read = reader.read(&mut buffer).await?;
+      if bytes_read == 0 {
+        break;
+      }
+      file.write_at(&buffer[..bytes_read], position)?;
+      position += bytes_read as u64;
+    }
+    file.flush()?;
+    Ok(position)
+  }
+
+  pub async fn exists_file(&self, path: &str) -> Result<objects::ExistFile, ApiError> {
+    let exists = tokio::fs::try_exists(self.resolve(path)).await?;
+    Ok(objects::ExistFile { exists })
+  }
+
+  pub async fn create_folder(&self, path: &str) -> Result<&str, ApiError> {
+    tokio::fs::create_dir_all(self.resolve(path)).await?;
+    Ok("okay")
+  }
+
+  pub async fn upload_small_file(&self, remote_path: &str, path_local: &str) -> Result<&str, ApiError> {
+    tokio::fs::copy(path_local, self.resolve(remote_path)).await?;
+    Ok("okay")
+  }
+
+  pub async fn initialize_file(&self, path: &str, size: u64) -> Result<&str, ApiError> {
+    let file = tokio::fs::File::create(self.resolve(path)).await?;
+    file.set_len(size).await?;
+    Ok("okay")
+  }
+
+  pub async fn write_chunk(&self, remote_path: &str, position: u64, buffer: Vec<u8>) -> Result<&str, ApiError> {
+    let mut target = tokio::fs::OpenOptions::new().write(true).open(self.resolve(remote_path)).await?;
+    target.seek(std::io::SeekFrom::Start(position)).await?;
+    target.write_all(&buffer).await?;
+    target.flush().await?;
+    Ok("okay")
+  }
+
+  pub async fn delete_file(&self, path: &str) -> Result<&str, ApiError> {
+    let target = self.resolve(path);
+    match tokio::fs::metadata(&target).await?.is_dir() {
+      true => tokio::fs::remove_dir_all(target).await?,
+      false => tokio::fs::remove_file(target).await?,
+    }
+    Ok("okay")
+  }
+
+  pub async fn move_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    let target = self.resolve(destination);
+    if let Some(parent) = target.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::rename(self.resolve(path), target).await?;
+    Ok("okay")
+  }
+
+  pub async fn copy_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    copy_recursive(&self.resolve(path), &self.resolve(destination)).await?;
+    Ok("okay")
+  }
+
+  pub async fn upload_file_chunks(&self, remote_path: &str, path_local: &str, size: u64, virtual_path: &str) -> Result<&str, ApiError> {
+    let source = tokio::fs::File::open(path_local).await?;
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE as usize, source);
+    let mut target = tokio::fs::OpenOptions::new().write(true).open(self.resolve(remote_path)).await?;
+    let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+    let mut offset: u64 = 0;
+
+    crate::detail!("Uploading {} ({}%)", virtual_path, 0_f32);
+
+    loop {
+      let bytes_read = reader.read(&mut buffer).await?;
+      if bytes_read == 0 {
+        break;
+      }
+      target.write_all(&buffer[..bytes_read]).await?;
+      offset += bytes_read as u64;
+      crate::detail!("Uploading {} ({}%)", virtual_path, utils::calc_file_uploaded(offset, size));
+    }
+    target.flush().await?;
+
+    Ok("okay")
+  }
+}
@@ -3,38 +3,318 @@
  * Copyright(c) Kintaro Ponce
  * MIT Licensed
  */
+use std::collections::BTreeMap;
+use std::fmt;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct Dirsync {
+  /// Selects the dir on the command line instead of its index.
+  #[serde(default)]
+  pub name: String,
+  /// Labels that select several dirs at once, like `nightly`.
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Name of the entry of `servers` the dir syncs with, the top level server when empty.
+  #[serde(default)]
+  pub server: String,
   pub remote_path: String,
   pub local_path: String,
-  pub sync_mode: String,
+  pub sync_mode: SyncMode,
+  #[serde(default)]
+  pub compare_mode: CompareMode,
+  #[serde(default)]
+  pub hash_algorithm: HashAlgorithm,
+  #[serde(default)]
+  pub encryption: Option<Encryption>,
+  #[serde(default)]
+  pub compression: Option<Compression>,
+  #[serde(default)]
+  pub delta: bool,
+  #[serde(default)]
+  pub mirror: bool,
+  #[serde(default = "default_trash")]
+  pub trash: bool,
+  #[serde(default)]
+  pub versioning: Option<Versioning>,
+  #[serde(default)]
+  pub retention: Option<Retention>,
+}
+
+/// Same values as a dir of the config file that only sets the required fields.
+impl Default for Dirsync {
+  fn default() -> Self {
+    Self {
+      name: String::new(),
+      tags: Vec::new(),
+      server: String::new(),
+      remote_path: String::new(),
+      local_path: String::new(),
+      sync_mode: SyncMode::default(),
+      compare_mode: CompareMode::default(),
+      hash_algorithm: HashAlgorithm::default(),
+      encryption: None,
+      compression: None,
+      delta: false,
+      mirror: false,
+      trash: default_trash(),
+      versioning: None,
+      retention: None,
+    }
+  }
+}
+
+/// What a dir does with its files, a config with any other value doesn't load.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+  /// Uploads the local changes.
+  Send,
+  /// Downloads the remote changes, also used by the dirs of `restore` and the path commands.
+  #[default]
+  Get,
+  /// Both ways, changes on both sides are conflicts.
+  Bidirectional,
+  /// Uploads dated snapshots that share unchanged files.
+  Backup,
+}
+
+impl SyncMode {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SyncMode::Send => "send",
+      SyncMode::Get => "get",
+      SyncMode::Bidirectional => "bidirectional",
+      SyncMode::Backup => "backup",
+    }
+  }
+}
+
+impl fmt::Display for SyncMode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
 }
 
+/// How many snapshots a `backup` dir keeps: the newest one of every day, week and month within each window.
 #[derive(Debug, Deserialize)]
-pub struct Configfile {
+pub struct Retention {
+  #[serde(default = "default_retention_daily")]
+  pub daily: u32,
+  #[serde(default = "default_retention_weekly")]
+  pub weekly: u32,
+  #[serde(default = "default_retention_monthly")]
+  pub monthly: u32,
+}
+
+fn default_retention_daily() -> u32 {
+  7
+}
+
+fn default_retention_weekly() -> u32 {
+  4
+}
+
+fn default_retention_monthly() -> u32 {
+  12
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Versioning {
+  #[serde(default)]
+  pub path: String,
+  #[serde(default)]
+  pub keep: usize,
+  #[serde(default)]
+  pub keep_days: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Compression {
+  #[serde(default = "default_compression_level")]
+  pub level: i32,
+  #[serde(default = "default_skip_extensions")]
+  pub skip_extensions: Vec<String>,
+}
+
+fn default_compression_level() -> i32 {
+  3
+}
+
+fn default_skip_extensions() -> Vec<String> {
+  [
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif",
+    "mp3", "ogg", "flac", "aac", "m4a", "opus",
+    "mp4", "mkv", "mov", "avi", "webm",
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar",
+    "docx", "xlsx", "pptx", "odt", "ods", "pdf",
+  ].iter().map(|extension| extension.to_string()).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Encryption {
+  #[serde(default)]
+  pub passphrase: String,
+  #[serde(default)]
+  pub key_file: String,
+  #[serde(default)]
+  pub encrypt_names: bool,
+  #[serde(default = "default_max_name_length")]
+  pub max_name_length: usize,
+}
+
+fn default_max_name_length() -> usize {
+  255
+}
+
+fn default_trash() -> bool {
+  true
+}
+
+/// How strictly a file that exists on both sides is compared, a config with any other value doesn't load.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareMode {
+  /// Only missing files are transferred.
+  #[default]
+  Name,
+  Size,
+  /// Like `Size`, local files modified since the last sync are also transferred.
+  SizeMtime,
+  /// By content hash, using `hash_algorithm`.
+  Checksum,
+}
+
+/// Hash of the `checksum` compare mode, a config with any other value doesn't load.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+  /// The one the server reports, so plain files are compared without reading the remote copy.
+  #[default]
+  Sha256,
+  Blake3,
+}
+
+impl HashAlgorithm {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      HashAlgorithm::Sha256 => "sha256",
+      HashAlgorithm::Blake3 => "blake3",
+    }
+  }
+}
+
+/// Where a dir syncs to and how to connect there.
+#[derive(Debug, Default, Deserialize)]
+pub struct Server {
+  #[serde(default)]
   pub base_url: String,
+  #[serde(default)]
   pub api_key: String,
+  /// Environment variable holding the api key, it overrides every other source when set.
+  #[serde(default)]
+  pub api_key_env: String,
+  /// File holding the api key, it must not be accessible by other users.
+  #[serde(default)]
+  pub api_key_file: String,
+  /// Shell command printing the api key, like `pass show k-cloud`.
+  #[serde(default)]
+  pub api_key_command: String,
+  /// Entry of the Secret Service keyring holding the api key.
+  #[serde(default)]
+  pub api_key_keyring: String,
+  #[serde(default)]
+  pub backend: String,
+  #[serde(default)]
+  pub tls: Option<Tls>,
+  /// Seconds a whole request may take, no limit with 0.
+  #[serde(default)]
+  pub timeout: u64,
+  /// Seconds to wait for the connection, no limit with 0.
+  #[serde(default)]
+  pub connect_timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tls {
+  /// PEM file with a certificate to trust besides the system ones, for self-signed servers.
+  #[serde(default)]
+  pub ca_cert: String,
+  #[serde(default)]
+  pub accept_invalid_certs: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Configfile {
+  /// The top level `base_url`, `api_key`, etc, used by the dirs without a `server`.
+  #[serde(flatten)]
+  pub server: Server,
+  #[serde(default)]
+  pub servers: BTreeMap<String, Server>,
   pub dirs: Vec<Dirsync>,
 }
 
 #[derive(Debug, Deserialize)]
-#[warn(non_snake_case)]
 pub struct User {
-  pub sessionId: String,
-  pub userId: String,
+  #[serde(rename = "sessionId")]
+  pub session_id: String,
+  #[serde(rename = "userId")]
+  pub user_id: String,
   pub username: String,
   // pub isadmin: bool
 }
 
-#[derive(Debug, Deserialize)]
+/// Type of a remote entry. Anything but files and folders, like links, keeps the name the server
+/// gives it and is left out of syncs.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum EntryType {
+  File,
+  Folder,
+  Other(String),
+}
+
+impl From<String> for EntryType {
+  fn from(name: String) -> Self {
+    match name.as_str() {
+      "file" => EntryType::File,
+      "folder" => EntryType::Folder,
+      _ => EntryType::Other(name),
+    }
+  }
+}
+
+impl From<EntryType> for String {
+  fn from(entry_type: EntryType) -> Self {
+    entry_type.to_string()
+  }
+}
+
+impl fmt::Display for EntryType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EntryType::File => f.write_str("file"),
+      EntryType::Folder => f.write_str("folder"),
+      EntryType::Other(name) => f.write_str(name),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct File {
   pub name: String,
-  pub r#type: String,
-  // pub size: i128,
-  // pub extension: String,
-  // pub mime_type: String
+  pub r#type: EntryType,
+  #[serde(default)]
+  pub size: Option<u64>,
+  #[serde(default)]
+  pub sha256: Option<String>,
+  #[serde(default)]
+  pub extension: Option<String>,
+  #[serde(default)]
+  pub mime_type: Option<String>,
+  /// Kept as raw JSON, it may come as a date string or as a timestamp.
+  #[serde(default)]
+  pub modified: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -42,13 +322,21 @@ pub struct FileList {
   pub list: Vec<File>
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct FileProperties {
-  // pub name: String,
-  pub r#type: String,
-  // pub size: i128,
-  // pub extension: String,
-  // pub mime_type: String
+  #[serde(default)]
+  pub name: Option<String>,
+  pub r#type: EntryType,
+  #[serde(default)]
+  pub size: Option<u64>,
+  #[serde(default)]
+  pub sha256: Option<String>,
+  #[serde(default)]
+  pub extension: Option<String>,
+  #[serde(default)]
+  pub mime_type: Option<String>,
+  #[serde(default)]
+  pub modified: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,8 +349,23 @@ pub struct SizeBody {
   pub size: u64
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationBody {
+  pub destination: String
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ScopesResp {
   pub r#type: String,
   pub scopes: Vec<String>
 }
+
+#[derive(Debug, Clone)]
+pub struct FileStat {
+  pub is_dir: bool,
+  pub size: u64,
+  pub mtime: i64,
+  pub mtime_nsec: i64,
+  pub dev: u64,
+  pub ino: u64,
+}
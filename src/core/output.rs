@@ -0,0 +1,63 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+use serde::Serialize;
+
+/// Only results, summaries and errors.
+pub const QUIET: u8 = 0;
+/// Also what happens to every file.
+pub const NORMAL: u8 = 1;
+/// Also every file looked at, transfer progress and connection details.
+pub const VERBOSE: u8 = 2;
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(NORMAL);
+
+pub fn set_verbosity(level: u8) {
+  VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+  VERBOSITY.load(Ordering::Relaxed)
+}
+
+pub fn print_json<T: Serialize>(value: &T) {
+  match serde_json::to_string_pretty(value) {
+    Ok(json) => println!("{}", json),
+    Err(err) => error(err),
+  }
+}
+
+pub fn error(err: impl Display) {
+  eprintln!("Error: {}", err);
+}
+
+/// Prints a warning, hidden by `--quiet`.
+pub fn warning(warning: impl Display) {
+  if verbosity() >= NORMAL {
+    eprintln!("Warning: {}", warning);
+  }
+}
+
+/// Prints a line about the file being worked on, hidden by `--quiet`.
+#[macro_export]
+macro_rules! progress {
+  ($($arg:tt)*) => {
+    if $crate::core::output::verbosity() >= $crate::core::output::NORMAL {
+      println!($($arg)*);
+    }
+  };
+}
+
+/// Prints a line only shown with `--verbose`.
+#[macro_export]
+macro_rules! detail {
+  ($($arg:tt)*) => {
+    if $crate::core::output::verbosity() >= $crate::core::output::VERBOSE {
+      println!($($arg)*);
+    }
+  };
+}
@@ -0,0 +1,151 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::fs;
+use std::io::Read;
+use sha2::{Digest, Sha256};
+use crate::core::api_conn::{ApiClient, ApiError, CHUNK_SIZE};
+use crate::core::local_conn::LocalClient;
+use crate::core::{credentials, file_conn, objects, utils};
+
+/// Remote side of a sync, either a k-cloud server or a local directory.
+pub enum Remote {
+  Api(ApiClient),
+  Local(LocalClient),
+}
+
+impl Remote {
+  pub fn new(server: &objects::Server) -> Result<Self, ApiError> {
+    if utils::is_local_backend(&server.base_url, &server.backend) {
+      return Ok(Remote::Local(LocalClient::new(&server.base_url)?));
+    }
+    let api_key = credentials::api_key(server)?;
+    Ok(Remote::Api(ApiClient::new(server, &api_key)?))
+  }
+
+  pub async fn get_file_properties(&self, path: &str) -> Result<objects::FileProperties, ApiError> {
+    match self {
+      Remote::Api(client) => client.get_file_properties(path).await,
+      Remote::Local(client) => client.get_file_properties(path).await,
+    }
+  }
+
+  pub async fn get_files_list(&self, path: &str) -> Result<objects::FileList, ApiError> {
+    match self {
+      Remote::Api(client) => client.get_files_list(path).await,
+      Remote::Local(client) => client.get_files_list(path).await,
+    }
+  }
+
+  pub async fn get_file(&self, path: &str, file: &fs::File) -> Result<u64, ApiError> {
+    match self {
+      Remote::Api(client) => client.get_file(path, file).await,
+      Remote::Local(client) => client.get_file(path, file).await,
+    }
+  }
+
+  pub async fn exists_file(&self, path: &str) -> Result<objects::ExistFile, ApiError> {
+    match self {
+      Remote::Api(client) => client.exists_file(path).await,
+      Remote::Local(client) => client.exists_file(path).await,
+    }
+  }
+
+  pub async fn create_folder(&self, path: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.create_folder(path).await,
+      Remote::Local(client) => client.create_folder(path).await,
+    }
+  }
+
+  pub async fn upload_small_file(&self, remote_path: &str, path_local: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.upload_small_file(remote_path, path_local).await,
+      Remote::Local(client) => client.upload_small_file(remote_path, path_local).await,
+    }
+  }
+
+  pub async fn initialize_file(&self, path: &str, size: u64) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.initialize_file(path, size).await,
+      Remote::Local(client) => client.initialize_file(path, size).await,
+    }
+  }
+
+  pub async fn write_chunk(&self, remote_path: &str, position: u64, buffer: Vec<u8>) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.write_chunk(remote_path, position, buffer).await,
+      Remote::Local(client) => client.write_chunk(remote_path, position, buffer).await,
+    }
+  }
+
+  pub async fn delete_file(&self, path: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.delete_file(path).await,
+      Remote::Local(client) => client.delete_file(path).await,
+    }
+  }
+
+  pub async fn move_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.move_file(path, destination).await,
+      Remote::Local(client) => client.move_file(path, destination).await,
+    }
+  }
+
+  pub async fn copy_file(&self, path: &str, destination: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.copy_file(path, destination).await,
+      Remote::Local(client) => client.copy_file(path, destination).await,
+    }
+  }
+
+  /// Creates `path` and its missing parents, the server may not create them on its own.
+  pub async fn create_folder_all(&self, path: &str) -> Result<(), ApiError> {
+    let mut current = String::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+      current = if current.is_empty() { segment.to_string() } else { utils::create_path(&current, segment) };
+      if !self.exists_file(&current).await?.exists {
+        self.create_folder(&current).await?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Uploads the `size` bytes read from `source` in `CHUNK_SIZE` pieces and returns their sha256.
+  pub async fn upload_reader(&self, remote_path: &str, source: &mut (dyn Read + Send), size: u64, virtual_path: &str) -> Result<String, ApiError> {
+    self.initialize_file(remote_path, size).await?;
+
+    let mut hasher = Sha256::new();
+    let mut offset: u64 = 0;
+    crate::detail!("Uploading {} ({}%)", virtual_path, utils::calc_file_uploaded(offset, size));
+
+    loop {
+      let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+      let filled = file_conn::read_chunk(source, &mut buffer)?;
+      if filled == 0 {
+        break;
+      }
+      buffer.truncate(filled);
+      hasher.update(&buffer);
+
+      self.write_chunk(remote_path, offset, buffer).await?;
+      offset += filled as u64;
+      crate::detail!("Uploading {} ({}%)", virtual_path, utils::calc_file_uploaded(offset, size));
+    }
+
+    if offset != size {
+      return Err(ApiError::Integrity { path: remote_path.to_string(), reason: format!("expected {} bytes, sent {}", size, offset) });
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+  }
+
+  pub async fn upload_file_chunks(&self, remote_path: &str, path_local: &str, size: u64, virtual_path: &str) -> Result<&str, ApiError> {
+    match self {
+      Remote::Api(client) => client.upload_file_chunks(remote_path, path_local, size, virtual_path).await,
+      Remote::Local(client) => client.upload_file_chunks(remote_path, path_local, size, virtual_path).await,
+    }
+  }
+}
@@ -0,0 +1,195 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::path::Path;
+use async_recursion::async_recursion;
+use crate::core::api_conn::ApiError;
+use crate::core::engine::{self, SyncContext};
+use crate::core::objects::EntryType;
+use crate::core::{backup, file_conn, utils};
+
+pub struct RestoreOptions {
+  /// What to do with files that already exist at the destination: `skip`, `overwrite` or `rename`.
+  pub overwrite: String,
+  pub include: Vec<String>,
+  pub exclude: Vec<String>,
+  /// Snapshot of a `backup` dir to restore from, `latest` for the newest one.
+  pub snapshot: String,
+}
+
+#[derive(Default)]
+struct RestoreCount {
+  restored: u32,
+  skipped: u32,
+  failed: u32,
+}
+
+fn selected(options: &RestoreOptions, relative: &str) -> bool {
+  let name = relative.rsplit('/').next().unwrap_or(relative);
+  let matches = |pattern: &String| utils::matches_pattern(pattern, relative) || utils::matches_pattern(pattern, name);
+  (options.include.is_empty() || options.include.iter().any(matches)) && !options.exclude.iter().any(matches)
+}
+
+/// Where the restored copy goes, `None` when an existing file has to be kept as it is.
+fn target_path(options: &RestoreOptions, local_path: &str) -> Option<String> {
+  if !file_conn::file_exists(local_path) {
+    return Some(local_path.to_string());
+  }
+  match options.overwrite.as_str() {
+    "overwrite" => Some(local_path.to_string()),
+    "rename" => Some(format!("{}.restored", local_path)),
+    _ => None,
+  }
+}
+
+// an existing folder receives the file, anything else is the new file name
+fn file_destination(destination: &str, name: &str) -> String {
+  match file_conn::is_dir(destination) {
+    Ok(true) => utils::create_path(destination, name),
+    _ => destination.to_string(),
+  }
+}
+
+async fn restore_file(ctx: &mut SyncContext<'_>, options: &RestoreOptions, remote_path: &str, local_path: &str, relative: &str, count: &mut RestoreCount) {
+  if !selected(options, relative) {
+    return;
+  }
+  let target = match target_path(options, local_path) {
+    Some(target) => target,
+    None => {
+      crate::progress!("skipped   {} (already exists)", relative);
+      count.skipped += 1;
+      return;
+    }
+  };
+
+  if ctx.dry_run {
+    crate::progress!("would restore {} -> {}", relative, target);
+    count.restored += 1;
+    return;
+  }
+
+  if let Some(parent) = Path::new(&target).parent() {
+    file_conn::create_dir(&parent.display().to_string());
+  }
+  crate::detail!("restoring {}", relative);
+  match engine::receive_file(ctx, remote_path, &target).await {
+    Ok(_) => {
+      crate::progress!("restored  {}", relative);
+      count.restored += 1;
+    },
+    Err(err) => {
+      eprintln!("Error: {} ({})", err, relative);
+      count.failed += 1;
+    },
+  }
+}
+
+#[async_recursion]
+async fn restore_folder(ctx: &mut SyncContext<'_>, options: &RestoreOptions, virtual_path: &str, local_path: &str, relative: &str, count: &mut RestoreCount) -> Result<(), ApiError> {
+  if !ctx.dry_run {
+    file_conn::create_dir(local_path);
+  }
+
+  for file in ctx.remote_list(virtual_path).await? {
+    if utils::is_reserved_name(&file.name) {
+      continue;
+    }
+    let item_path = utils::create_path(virtual_path, &file.name);
+    let item_local = utils::create_path(local_path, &file.name);
+    let item_relative = utils::create_path(relative, &file.name);
+
+    match &file.r#type {
+      EntryType::Folder => restore_folder(ctx, options, &item_path, &item_local, &item_relative, count).await?,
+      EntryType::File => {
+        let remote_path = ctx.remote_path(&item_path)?;
+        restore_file(ctx, options, &remote_path, &item_local, &item_relative, count).await;
+      },
+      EntryType::Other(entry_type) => {
+        crate::progress!("skipped {}, {} entries can't be restored", item_relative, entry_type);
+        count.skipped += 1;
+      },
+    }
+  }
+  Ok(())
+}
+
+async fn restore_snapshot(ctx: &mut SyncContext<'_>, options: &RestoreOptions, virtual_path: &str, destination: &str, count: &mut RestoreCount) -> Result<(), ApiError> {
+  let stamp = match options.snapshot.as_str() {
+    "latest" => backup::list_snapshots(ctx.dir, ctx.remote).await?.pop().unwrap_or_default(),
+    stamp => stamp.to_string(),
+  };
+  let manifest = match backup::load_manifest(ctx, &stamp).await? {
+    Some(manifest) => manifest,
+    None => return Err(ApiError::Integrity { path: backup::snapshot_path(ctx.dir, &stamp), reason: String::from("snapshot has no manifest") }),
+  };
+  crate::progress!("restoring from snapshot {}", manifest.snapshot);
+
+  // a file restores to `destination` itself, a folder restores its content into it
+  let relative_to = |path: &str| -> Option<String> {
+    if virtual_path.is_empty() {
+      return Some(path.to_string());
+    }
+    if path == virtual_path {
+      return Some(String::new());
+    }
+    path.strip_prefix(&format!("{}/", virtual_path)).map(|relative| relative.to_string())
+  };
+
+  if !manifest.files.contains_key(virtual_path) && !ctx.dry_run {
+    file_conn::create_dir(destination);
+  }
+  for folder in manifest.folders.iter() {
+    if let Some(relative) = relative_to(folder) && selected(options, &relative) && !ctx.dry_run {
+      file_conn::create_dir(&utils::side_path(destination, &relative));
+    }
+  }
+  for (path, entry) in manifest.files.iter() {
+    let relative = match relative_to(path) {
+      Some(relative) => relative,
+      None => continue,
+    };
+    let remote_path = ctx.remote_path_in(&backup::snapshot_path(ctx.dir, &entry.stored_in), path)?;
+    if relative.is_empty() {
+      let name = path.rsplit('/').next().unwrap_or(path);
+      restore_file(ctx, options, &remote_path, &file_destination(destination, name), name, count).await;
+    } else {
+      restore_file(ctx, options, &remote_path, &utils::create_path(destination, &relative), &relative, count).await;
+    }
+  }
+  Ok(())
+}
+
+/// Downloads `virtual_path` (a file or a folder, relative to the remote root of `ctx`) to `destination`.
+pub async fn restore(ctx: &mut SyncContext<'_>, virtual_path: &str, destination: &str, options: &RestoreOptions) -> Result<String, Box<dyn std::error::Error>> {
+  let virtual_path = virtual_path.trim_matches('/');
+  let mut count = RestoreCount::default();
+
+  if !options.snapshot.is_empty() {
+    restore_snapshot(ctx, options, virtual_path, destination, &mut count).await?;
+  } else {
+    let remote_path = ctx.remote_path(virtual_path)?;
+    let properties = ctx.remote.get_file_properties(&remote_path).await?;
+    match properties.r#type {
+      EntryType::Folder => restore_folder(ctx, options, virtual_path, destination, "", &mut count).await?,
+      EntryType::File => {
+        let name = virtual_path.rsplit('/').next().unwrap_or(virtual_path);
+        restore_file(ctx, options, &remote_path, &file_destination(destination, name), name, &mut count).await;
+      },
+      EntryType::Other(entry_type) => {
+        return Err(Box::new(ApiError::InvalidPath { path: remote_path, reason: format!("{} entries can't be restored", entry_type) }));
+      },
+    }
+  }
+
+  if ctx.dry_run {
+    return Ok(format!("dry run: {} files would be restored, {} skipped", count.restored, count.skipped));
+  }
+  let summary = format!("{} restored, {} skipped", count.restored, count.skipped);
+  if count.failed > 0 {
+    return Ok(format!("restore finished with {} errors ({})", count.failed, summary));
+  }
+  Ok(format!("restore finished ({})", summary))
+}
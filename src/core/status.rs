@@ -0,0 +1,72 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use crate::core::api_conn::ApiError;
+use crate::core::engine::{self, Action, SyncContext};
+use crate::core::objects::SyncMode;
+use crate::core::{backup, utils};
+
+/// Pending work of a dir, counted from the sync plan without executing it.
+#[derive(Default, Serialize)]
+pub struct Status {
+  pub uploads: u32,
+  pub upload_bytes: u64,
+  pub downloads: u32,
+  pub download_bytes: u64,
+  pub conflicts: u32,
+  pub deletions: u32,
+  pub new_folders: u32,
+}
+
+pub async fn dir_status(ctx: &mut SyncContext<'_>) -> Result<Status, ApiError> {
+  let mut status = Status::default();
+
+  if ctx.dir.sync_mode == SyncMode::Backup {
+    let (_, changed, bytes) = backup::pending(ctx).await?;
+    status.uploads = changed.len() as u32;
+    status.upload_bytes = bytes;
+    return Ok(status);
+  }
+
+  for item in engine::plan_sync(ctx).await?.iter() {
+    match item.action {
+      Action::Upload => {
+        status.uploads += 1;
+        status.upload_bytes += item.size;
+      },
+      Action::Download => {
+        status.downloads += 1;
+        status.download_bytes += item.size;
+      },
+      Action::Conflict => status.conflicts += 1,
+      Action::DeleteLocal | Action::DeleteRemote => status.deletions += 1,
+      Action::CreateLocalDir | Action::CreateRemoteDir => status.new_folders += 1,
+      Action::Unchanged => {},
+    }
+  }
+  Ok(status)
+}
+
+fn format_last_sync(last_sync: Option<u64>) -> String {
+  match last_sync.and_then(|seconds| DateTime::from_timestamp(seconds as i64, 0)) {
+    Some(time) => time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+    None => String::from("never"),
+  }
+}
+
+pub async fn display_status(ctx: &mut SyncContext<'_>) -> Result<(), ApiError> {
+  println!("{} <-> {} ({})", ctx.dir.local_path, ctx.dir.remote_path, ctx.dir.sync_mode);
+  println!("  last sync:   {}", format_last_sync(ctx.state.last_sync));
+
+  let status = dir_status(ctx).await?;
+  println!("  uploads:     {} files, {}", status.uploads, utils::format_bytes(status.upload_bytes));
+  println!("  downloads:   {} files, {}", status.downloads, utils::format_bytes(status.download_bytes));
+  println!("  conflicts:   {}", status.conflicts);
+  println!("  deletions:   {}", status.deletions);
+  println!("  new folders: {}", status.new_folders);
+  Ok(())
+}
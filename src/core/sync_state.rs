@@ -0,0 +1,147 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use crate::core::file_conn;
+use crate::core::objects::{Dirsync, FileStat, HashAlgorithm};
+
+/// What the local file looked like the last time it was transferred or found in sync.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileRecord {
+  pub size: u64,
+  pub mtime: i64,
+  pub mtime_nsec: i64,
+  #[serde(default)]
+  pub hash: Option<String>,
+  #[serde(default)]
+  pub remote_size: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SyncState {
+  #[serde(default)]
+  pub last_sync: Option<u64>,
+  #[serde(default)]
+  pub files: BTreeMap<String, FileRecord>,
+  /// Hashes of every `CHUNK_SIZE` block of the files uploaded with delta uploads.
+  #[serde(default)]
+  pub chunks: BTreeMap<String, Vec<String>>,
+}
+
+/// File hashes keyed by algorithm, device, inode, size and mtime.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HashCache {
+  #[serde(default)]
+  entries: HashMap<String, String>,
+  #[serde(skip)]
+  used: HashSet<String>,
+}
+
+impl HashCache {
+  pub fn hash(&mut self, path: &str, stat: &FileStat, algorithm: HashAlgorithm) -> io::Result<String> {
+    let key = format!("{}:{}:{}:{}:{}.{}", algorithm.as_str(), stat.dev, stat.ino, stat.size, stat.mtime, stat.mtime_nsec);
+
+    let hash = match self.entries.get(&key) {
+      Some(hash) => hash.clone(),
+      None => {
+        let hash = file_conn::hash_file_with(path, algorithm)?;
+        self.entries.insert(key.clone(), hash.clone());
+        hash
+      }
+    };
+    self.used.insert(key);
+    Ok(hash)
+  }
+
+  /// Drops the entries of files that were not seen during this run.
+  pub fn prune(&mut self) {
+    let used = &self.used;
+    self.entries.retain(|key, _| used.contains(key));
+  }
+}
+
+pub fn record_from_stat(stat: &FileStat, hash: Option<String>, remote_size: Option<u64>) -> FileRecord {
+  FileRecord {
+    size: stat.size,
+    mtime: stat.mtime,
+    mtime_nsec: stat.mtime_nsec,
+    hash,
+    remote_size,
+  }
+}
+
+fn state_dir() -> PathBuf {
+  if let Ok(dir) = env::var("XDG_STATE_HOME") && !dir.is_empty() {
+    return PathBuf::from(dir).join("k-cloud-sync");
+  }
+  let home = env::var("HOME").unwrap_or_else(|_| String::from("."));
+  PathBuf::from(home).join(".local").join("state").join("k-cloud-sync")
+}
+
+/// Scratch file for a transfer, under the state dir so nothing is left behind in the synced folders.
+pub fn temp_file(kind: &str) -> io::Result<String> {
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+  let dir = state_dir().join("tmp");
+  fs::create_dir_all(&dir)?;
+  let name = format!("{}-{}-{}", kind, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+  Ok(dir.join(name).display().to_string())
+}
+
+fn state_id(dir: &Dirsync) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(dir.local_path.as_bytes());
+  hasher.update(b"\n");
+  hasher.update(dir.remote_path.as_bytes());
+  // dirs on the top level server keep the ids they had before named servers existed
+  if !dir.server.is_empty() {
+    hasher.update(b"\n");
+    hasher.update(dir.server.as_bytes());
+  }
+  let digest = format!("{:x}", hasher.finalize());
+  digest[..16].to_string()
+}
+
+fn state_file(dir: &Dirsync, suffix: &str) -> PathBuf {
+  state_dir().join(format!("{}{}.json", state_id(dir), suffix))
+}
+
+fn load_json<T: DeserializeOwned + Default>(path: PathBuf) -> T {
+  match fs::read_to_string(&path) {
+    Ok(raw_json) => serde_json::from_str(&raw_json).unwrap_or_default(),
+    Err(_) => T::default(),
+  }
+}
+
+fn save_json<T: Serialize>(path: PathBuf, value: &T) -> io::Result<()> {
+  fs::create_dir_all(state_dir())?;
+  let raw_json = serde_json::to_string(value)?;
+  let tmp_path = path.with_extension("json.tmp");
+  fs::write(&tmp_path, raw_json)?;
+  fs::rename(tmp_path, path)
+}
+
+pub fn load_state(dir: &Dirsync) -> SyncState {
+  load_json(state_file(dir, ""))
+}
+
+pub fn save_state(dir: &Dirsync, state: &SyncState) -> io::Result<()> {
+  save_json(state_file(dir, ""), state)
+}
+
+pub fn load_hash_cache(dir: &Dirsync) -> HashCache {
+  load_json(state_file(dir, ".hashes"))
+}
+
+pub fn save_hash_cache(dir: &Dirsync, cache: &HashCache) -> io::Result<()> {
+  save_json(state_file(dir, ".hashes"), cache)
+}
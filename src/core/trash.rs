@@ -0,0 +1,65 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use chrono::{Duration, Local, NaiveDate};
+use crate::core::api_conn::ApiError;
+use crate::core::objects::Dirsync;
+use crate::core::remote::Remote;
+use crate::core::utils;
+
+const TRASH_DIR: &str = ".kcloud-trash";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+pub fn trash_path(dir: &Dirsync) -> String {
+  utils::create_path(&dir.remote_path, TRASH_DIR)
+}
+
+/// Moves `remote_path` (a file or folder inside `dir.remote_path`) to today's trash folder.
+/// Returns where it was moved to.
+pub async fn move_to_trash(dir: &Dirsync, remote: &Remote, remote_path: &str) -> Result<String, ApiError> {
+  let relative = remote_path.strip_prefix(&dir.remote_path).unwrap_or(remote_path).trim_start_matches('/');
+  let now = Local::now();
+  let day_path = utils::create_path(&trash_path(dir), &now.format(DATE_FORMAT).to_string());
+
+  let mut destination = utils::create_path(&day_path, relative);
+  // the same path deleted twice on one day keeps both copies
+  if remote.exists_file(&destination).await?.exists {
+    destination = format!("{}.{}", destination, now.format("%H-%M-%S"));
+  }
+  if let Some((parent, _)) = destination.rsplit_once('/') {
+    remote.create_folder_all(parent).await?;
+  }
+
+  remote.move_file(remote_path, &destination).await?;
+  Ok(destination)
+}
+
+/// Deletes the trash folders of `dir` older than `older_than_days`, all of them with `None`.
+/// Returns the number of folders deleted, or that would be with `dry_run`.
+pub async fn purge(dir: &Dirsync, remote: &Remote, older_than_days: Option<u64>, dry_run: bool) -> Result<u32, ApiError> {
+  let trash = trash_path(dir);
+  if !remote.exists_file(&trash).await?.exists {
+    return Ok(0);
+  }
+  let oldest_kept = older_than_days.map(|days| Local::now().date_naive() - Duration::days(days as i64));
+
+  let mut purged: u32 = 0;
+  for folder in remote.get_files_list(&trash).await?.list.iter() {
+    let day = match NaiveDate::parse_from_str(&folder.name, DATE_FORMAT) {
+      Ok(day) => day,
+      Err(_) => continue,
+    };
+    if oldest_kept.is_none_or(|oldest_kept| day < oldest_kept) {
+      if dry_run {
+        crate::progress!("would purge {}", folder.name);
+      } else {
+        remote.delete_file(&utils::create_path(&trash, &folder.name)).await?;
+        crate::progress!("purged    {}", folder.name);
+      }
+      purged += 1;
+    }
+  }
+  Ok(purged)
+}
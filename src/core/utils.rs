@@ -11,6 +11,60 @@ pub fn create_path(virtual_path: &str, dir_name: &str) -> String {
   // return format!("{}/{}", virtual_path, dir_name);
 }
 
+/// Joins a sync root with a path relative to it, the root itself for an empty path.
+pub fn side_path(root: &str, virtual_path: &str) -> String {
+  if virtual_path.is_empty() {
+    return root.to_string();
+  }
+  create_path(root, virtual_path)
+}
+
+/// Names starting with `.kcloud` belong to the tool itself and are never synced.
+pub fn is_reserved_name(name: &str) -> bool {
+  name.starts_with(".kcloud")
+}
+
+/// Temporary name a download is written to before replacing `local_path`.
+pub fn part_path(local_path: &str) -> String {
+  temp_path(local_path, "part")
+}
+
+/// Name next to `local_path` that a new copy is written under before it replaces `local_path`.
+pub fn temp_path(local_path: &str, kind: &str) -> String {
+  let path = Path::new(local_path);
+  let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+  path.with_file_name(format!(".kcloud-{}-{}", kind, file_name)).display().to_string()
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters and `?` for one.
+pub fn matches_pattern(pattern: &str, text: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let text: Vec<char> = text.chars().collect();
+  let (mut p, mut t) = (0, 0);
+  let mut backtrack: Option<(usize, usize)> = None;
+
+  while t < text.len() {
+    if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+      p += 1;
+      t += 1;
+    } else if p < pattern.len() && pattern[p] == '*' {
+      backtrack = Some((p, t));
+      p += 1;
+    } else if let Some((star, matched)) = backtrack {
+      p = star + 1;
+      t = matched + 1;
+      backtrack = Some((star, matched + 1));
+    } else {
+      return false;
+    }
+  }
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub fn is_local_backend(base_url: &str, backend: &str) -> bool {
+  backend == "local" || base_url.starts_with("file://")
+}
+
 pub fn is_authorized(scopes: &ScopesResp) -> bool {
   let scopes_list = scopes.scopes.clone();
   let mut scopes_needed_found: u8 = 0;
@@ -46,21 +100,94 @@ pub fn calc_file_uploaded(bytes_uploaded: u64, size: u64) -> f32 {
   percentage
 }
 
-pub fn display_dirs_list(dirs_list: &Vec<Dirsync>) {
+/// Human readable size, like `1.5 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+  let units = ["B", "KB", "MB", "GB", "TB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < units.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  match unit {
+    0 => format!("{} B", bytes),
+    _ => format!("{:.1} {}", value, units[unit]),
+  }
+}
+
+/// Date of a remote entry, timestamps may be in seconds or milliseconds.
+pub fn format_modified(modified: &Option<serde_json::Value>) -> String {
+  let timestamp = match modified {
+    Some(serde_json::Value::Number(number)) => number.as_i64(),
+    Some(serde_json::Value::String(date)) => return date.clone(),
+    _ => None,
+  };
+  let seconds = match timestamp {
+    Some(timestamp) if timestamp > 100_000_000_000 => timestamp / 1000,
+    Some(timestamp) => timestamp,
+    None => return String::from("-"),
+  };
+  match chrono::DateTime::from_timestamp(seconds, 0) {
+    Some(time) => time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string(),
+    None => String::from("-"),
+  }
+}
+
+pub fn display_dirs_list(dirs_list: &[Dirsync]) {
   println!("list:");
   for (i, dir) in dirs_list.iter().enumerate() {
-    println!("{}. {}", i, dir.sync_mode);
+    match dir.name.is_empty() {
+      true => println!("{}. {}", i, dir.sync_mode),
+      false => println!("{}. {} ({})", i, dir.name, dir.sync_mode),
+    }
     println!("  local:{}", dir.local_path);
     println!(" remote:{}", dir.remote_path);
+    if !dir.server.is_empty() {
+      println!(" server:{}", dir.server);
+    }
+    if !dir.tags.is_empty() {
+      println!("   tags:{}", dir.tags.join(", "));
+    }
   }
 }
 
-pub fn display_help() {
-  println!("usage:");
-  println!("./app [action] [file] [dir]");
-  println!("actions:");
-  println!("1. list: show list of dirs");
-  println!("2. sync: sync files");
-  println!("file: file name");
-  println!("dir: optional dir index only if you want to sync one dir");
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn literal_patterns_match_the_whole_text() {
+    assert!(matches_pattern("notes.txt", "notes.txt"));
+    assert!(!matches_pattern("notes.txt", "notes.txt.bak"));
+    assert!(!matches_pattern("notes", "my notes"));
+    assert!(matches_pattern("", ""));
+    assert!(!matches_pattern("", "a"));
+  }
+
+  #[test]
+  fn question_mark_matches_one_character() {
+    assert!(matches_pattern("photo-?.jpg", "photo-1.jpg"));
+    assert!(matches_pattern("año-?", "año-ñ"));
+    assert!(!matches_pattern("photo-?.jpg", "photo-12.jpg"));
+    assert!(!matches_pattern("?", ""));
+  }
+
+  #[test]
+  fn star_matches_any_run_of_characters() {
+    assert!(matches_pattern("*", ""));
+    assert!(matches_pattern("*.jpg", "a.jpg"));
+    assert!(matches_pattern("*.jpg", ".jpg"));
+    assert!(matches_pattern("docs/*/draft*", "docs/2026/draft-final"));
+    assert!(!matches_pattern("*.jpg", "a.jpeg"));
+    assert!(!matches_pattern("a*b", "acbd"));
+  }
+
+  #[test]
+  fn star_backtracks_over_partial_matches() {
+    assert!(matches_pattern("*ab", "aab"));
+    assert!(matches_pattern("a*a*a", "aaaa"));
+    assert!(matches_pattern("*?x", "yyx"));
+    assert!(!matches_pattern("a*a*a", "aa"));
+    assert!(!matches_pattern("*?x", "x"));
+  }
 }
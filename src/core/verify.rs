@@ -0,0 +1,232 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use async_recursion::async_recursion;
+use std::collections::{BTreeSet, HashSet};
+use serde::Serialize;
+use crate::core::api_conn::ApiError;
+use crate::core::engine::SyncContext;
+use crate::core::objects::{EntryType, File, FileStat, HashAlgorithm, SyncMode};
+use crate::core::{backup, compare, file_conn, utils};
+
+/// One difference found by `verify`, like `size mismatch` with its path.
+#[derive(Serialize)]
+pub struct Difference {
+  pub kind: &'static str,
+  pub path: String,
+  #[serde(skip_serializing_if = "String::is_empty")]
+  pub detail: String,
+}
+
+/// Differences found by `verify`, nothing is transferred or changed while looking for them.
+#[derive(Default, Serialize)]
+pub struct VerifyReport {
+  pub checked: u32,
+  pub missing_remote: u32,
+  pub missing_local: u32,
+  pub type_mismatch: u32,
+  pub size_mismatch: u32,
+  pub mtime_mismatch: u32,
+  pub checksum_mismatch: u32,
+  /// Files whose checksum couldn't be compared because no remote hash is known.
+  pub unverified: u32,
+  pub found: Vec<Difference>,
+}
+
+impl VerifyReport {
+  fn note(&mut self, kind: &'static str, path: &str, detail: String) {
+    match detail.is_empty() {
+      true => crate::progress!("{:<17} {}", kind, path),
+      false => crate::progress!("{:<17} {} ({})", kind, path, detail),
+    }
+    self.found.push(Difference { kind, path: path.to_string(), detail });
+  }
+
+  pub fn differences(&self) -> u32 {
+    self.missing_remote + self.missing_local + self.type_mismatch + self.size_mismatch + self.mtime_mismatch + self.checksum_mismatch
+  }
+
+  pub fn summary(&self) -> String {
+    format!(
+      "{} files checked: {} missing remote, {} missing local, {} type mismatches, {} size mismatches, {} mtime mismatches, {} checksum mismatches, {} not checksummed",
+      self.checked, self.missing_remote, self.missing_local, self.type_mismatch, self.size_mismatch, self.mtime_mismatch, self.checksum_mismatch, self.unverified,
+    )
+  }
+}
+
+fn compare_checksum(ctx: &mut SyncContext<'_>, virtual_path: &str, local: &FileStat, known_hash: Option<(HashAlgorithm, String)>, report: &mut VerifyReport) -> Result<(), ApiError> {
+  let (algorithm, known) = match known_hash {
+    Some(known_hash) => known_hash,
+    None => {
+      report.unverified += 1;
+      return Ok(());
+    }
+  };
+  let local_hash = ctx.hashes.hash(&ctx.local_path(virtual_path), local, algorithm)?;
+  if !local_hash.eq_ignore_ascii_case(&known) {
+    report.note("checksum mismatch", virtual_path, String::new());
+    report.checksum_mismatch += 1;
+  }
+  Ok(())
+}
+
+fn verify_file(ctx: &mut SyncContext<'_>, virtual_path: &str, local: &FileStat, remote: &File, checksum: bool, report: &mut VerifyReport) -> Result<(), ApiError> {
+  report.checked += 1;
+  let record = ctx.state.files.get(virtual_path).cloned();
+
+  let size_differs = match (compare::expected_remote_size(ctx.dir, local, &remote.name), remote.size) {
+    (Some(expected), Some(size)) => expected != size,
+    // compressed sizes can't be predicted, only a local change since the last transfer shows
+    _ => record.as_ref().is_some_and(|r| r.size != local.size),
+  };
+  if size_differs {
+    let remote_size = remote.size.map(|size| size.to_string()).unwrap_or(String::from("?"));
+    report.note("size mismatch", virtual_path, format!("local {} bytes, remote {} bytes", local.size, remote_size));
+    report.size_mismatch += 1;
+    return Ok(());
+  }
+  // the server keeps no mtime, the local one is compared with the one recorded at the last transfer
+  if let Some(r) = &record && (r.mtime != local.mtime || r.mtime_nsec != local.mtime_nsec) {
+    report.note("mtime mismatch", virtual_path, String::from("changed since the last sync"));
+    report.mtime_mismatch += 1;
+  }
+
+  if checksum {
+    let is_plain = ctx.dir.encryption.is_none() && compare::expected_remote_size(ctx.dir, local, &remote.name) == Some(local.size);
+    let known_hash = match (&remote.sha256, record.and_then(|r| r.hash)) {
+      (Some(remote_hash), _) if is_plain => Some((HashAlgorithm::Sha256, remote_hash.clone())),
+      (_, Some(recorded)) => Some((ctx.dir.hash_algorithm, recorded)),
+      _ => None,
+    };
+    compare_checksum(ctx, virtual_path, local, known_hash, report)?;
+  }
+  Ok(())
+}
+
+#[async_recursion]
+async fn verify_dir(ctx: &mut SyncContext<'_>, virtual_path: &str, local_exists: bool, remote_exists: bool, checksum: bool, report: &mut VerifyReport) -> Result<(), ApiError> {
+  let local_names = if local_exists { file_conn::file_list(&ctx.local_path(virtual_path)) } else { Vec::new() };
+  let remote_list = if remote_exists { ctx.remote_list(virtual_path).await? } else { Vec::new() };
+
+  let mut names: BTreeSet<String> = local_names.into_iter().collect();
+  names.extend(remote_list.iter().map(|file| file.name.clone()));
+
+  for name in names.iter().filter(|name| !utils::is_reserved_name(name)) {
+    let item_path = utils::create_path(virtual_path, name);
+    let local_stat = if local_exists { file_conn::get_file_stat(&ctx.local_path(&item_path)).ok() } else { None };
+    let remote_file = remote_list.iter().find(|file| &file.name == name);
+
+    if let Some(File { r#type: EntryType::Other(entry_type), .. }) = remote_file {
+      crate::detail!("skipped {}, {} entries are not synced", item_path, entry_type);
+      continue;
+    }
+
+    match (local_stat, remote_file) {
+      (Some(local), Some(remote_f)) => {
+        let remote_is_dir = remote_f.r#type == EntryType::Folder;
+        if local.is_dir != remote_is_dir {
+          report.note("type mismatch", &item_path, String::from("file on one side, folder on the other"));
+          report.type_mismatch += 1;
+        } else if local.is_dir {
+          verify_dir(ctx, &item_path, true, true, checksum, report).await?;
+        } else {
+          verify_file(ctx, &item_path, &local, remote_f, checksum, report)?;
+        }
+      },
+      (Some(local), None) => {
+        report.note("missing remote", &item_path, String::new());
+        report.missing_remote += 1;
+        if local.is_dir {
+          verify_dir(ctx, &item_path, true, false, checksum, report).await?;
+        }
+      },
+      (None, Some(remote_f)) => {
+        report.note("missing local", &item_path, String::new());
+        report.missing_local += 1;
+        if remote_f.r#type == EntryType::Folder {
+          verify_dir(ctx, &item_path, false, true, checksum, report).await?;
+        }
+      },
+      (None, None) => {},
+    }
+  }
+  Ok(())
+}
+
+/// Compares the local dir with the latest snapshot and checks that every file it points to is stored.
+async fn verify_snapshot(ctx: &mut SyncContext<'_>, checksum: bool, report: &mut VerifyReport) -> Result<(), ApiError> {
+  let stamp = match backup::list_snapshots(ctx.dir, ctx.remote).await?.pop() {
+    Some(stamp) => stamp,
+    None => return Err(ApiError::Integrity { path: ctx.dir.remote_path.clone(), reason: String::from("no snapshot found") }),
+  };
+  let manifest = match backup::load_manifest(ctx, &stamp).await? {
+    Some(manifest) => manifest,
+    None => return Err(ApiError::Integrity { path: backup::snapshot_path(ctx.dir, &stamp), reason: String::from("snapshot has no manifest") }),
+  };
+  crate::progress!("verifying snapshot {}", stamp);
+
+  let mut folders: Vec<String> = Vec::new();
+  let mut files: Vec<(String, FileStat)> = Vec::new();
+  if file_conn::file_exists(&ctx.dir.local_path) {
+    backup::collect_local(ctx, "", &mut folders, &mut files)?;
+  }
+
+  for (virtual_path, local) in files.iter() {
+    let entry = match manifest.files.get(virtual_path) {
+      Some(entry) => entry,
+      None => {
+        report.note("missing remote", virtual_path, String::new());
+        report.missing_remote += 1;
+        continue;
+      }
+    };
+    report.checked += 1;
+
+    let stored_path = ctx.remote_path_in(&backup::snapshot_path(ctx.dir, &entry.stored_in), virtual_path)?;
+    let stored_size = match ctx.remote.exists_file(&stored_path).await?.exists {
+      true => ctx.remote.get_file_properties(&stored_path).await?.size,
+      false => {
+        report.note("missing remote", virtual_path, format!("not found in snapshot {}", entry.stored_in));
+        report.missing_remote += 1;
+        continue;
+      }
+    };
+    if entry.size != local.size || stored_size.is_some_and(|size| size != entry.remote_size) {
+      report.note("size mismatch", virtual_path, format!("local {} bytes, snapshot {} bytes", local.size, entry.size));
+      report.size_mismatch += 1;
+      continue;
+    }
+    if entry.mtime != local.mtime || entry.mtime_nsec != local.mtime_nsec {
+      report.note("mtime mismatch", virtual_path, String::new());
+      report.mtime_mismatch += 1;
+    }
+    if checksum {
+      let known_hash = entry.hash.clone().map(|hash| (ctx.dir.hash_algorithm, hash));
+      compare_checksum(ctx, virtual_path, local, known_hash, report)?;
+    }
+  }
+
+  let local_paths: HashSet<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+  for virtual_path in manifest.files.keys() {
+    if !local_paths.contains(virtual_path.as_str()) {
+      report.note("missing local", virtual_path, String::new());
+      report.missing_local += 1;
+    }
+  }
+  Ok(())
+}
+
+/// Walks both sides of `ctx.dir` and reports every difference, strictly read-only.
+pub async fn verify(ctx: &mut SyncContext<'_>, checksum: bool) -> Result<VerifyReport, ApiError> {
+  let mut report = VerifyReport::default();
+
+  if ctx.dir.sync_mode == SyncMode::Backup {
+    verify_snapshot(ctx, checksum, &mut report).await?;
+  } else {
+    let local_exists = file_conn::file_exists(&ctx.dir.local_path);
+    verify_dir(ctx, "", local_exists, true, checksum, &mut report).await?;
+  }
+  Ok(report)
+}
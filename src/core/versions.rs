@@ -0,0 +1,168 @@
+/*
+ * k-cloud-sync-rust
+ * Copyright(c) Kintaro Ponce
+ * MIT Licensed
+ */
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{Duration, Local, NaiveDateTime};
+use serde::Serialize;
+use crate::core::objects::{Dirsync, Versioning};
+use crate::core::utils;
+
+const VERSIONS_DIR: &str = ".kcloud-versions";
+const STAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+#[derive(Serialize)]
+pub struct Version {
+  pub virtual_path: String,
+  pub stamp: String,
+  pub path: PathBuf,
+  pub size: u64,
+}
+
+pub fn store_path(dir: &Dirsync, versioning: &Versioning) -> PathBuf {
+  if versioning.path.is_empty() {
+    return Path::new(&dir.local_path).join(VERSIONS_DIR);
+  }
+  PathBuf::from(&versioning.path)
+}
+
+// rename only works inside one filesystem, a separate store may live on another one
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+  if fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  fs::copy(from, to)?;
+  fs::remove_file(from)
+}
+
+fn collect_versions(root: &Path, current: &Path, versions: &mut Vec<Version>) -> io::Result<()> {
+  if !current.exists() {
+    return Ok(());
+  }
+  for entry in fs::read_dir(current)? {
+    let entry = entry?;
+    let path = entry.path();
+    if entry.file_type()?.is_dir() {
+      collect_versions(root, &path, versions)?;
+      continue;
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(&path).display().to_string();
+    let (virtual_path, stamp) = match relative.rsplit_once('.') {
+      Some(parts) => parts,
+      None => continue,
+    };
+    if NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).is_err() {
+      continue;
+    }
+    versions.push(Version {
+      virtual_path: virtual_path.to_string(),
+      stamp: stamp.to_string(),
+      size: entry.metadata()?.len(),
+      path,
+    });
+  }
+  Ok(())
+}
+
+/// Versions kept for `filter` (a file or a folder, everything when empty), newest first.
+pub fn list_versions(dir: &Dirsync, filter: &str) -> io::Result<Vec<Version>> {
+  let versioning = match &dir.versioning {
+    Some(versioning) => versioning,
+    None => return Ok(Vec::new()),
+  };
+  let root = store_path(dir, versioning);
+  let mut versions: Vec<Version> = Vec::new();
+  collect_versions(&root, &root, &mut versions)?;
+
+  let filter = filter.trim_matches('/');
+  versions.retain(|version| filter.is_empty() || version.virtual_path == filter || version.virtual_path.starts_with(&format!("{}/", filter)));
+  versions.sort_by(|a, b| a.virtual_path.cmp(&b.virtual_path).then(b.stamp.cmp(&a.stamp)));
+  Ok(versions)
+}
+
+fn apply_retention(dir: &Dirsync, versioning: &Versioning, virtual_path: &str) -> io::Result<()> {
+  let oldest_kept = (Local::now() - Duration::days(versioning.keep_days as i64)).naive_local();
+  let versions = list_versions(dir, virtual_path)?;
+
+  for (index, version) in versions.iter().filter(|version| version.virtual_path == virtual_path).enumerate() {
+    let too_many = versioning.keep > 0 && index >= versioning.keep;
+    let too_old = versioning.keep_days > 0
+      && NaiveDateTime::parse_from_str(&version.stamp, STAMP_FORMAT).is_ok_and(|stamp| stamp < oldest_kept);
+    if too_many || too_old {
+      fs::remove_file(&version.path)?;
+    }
+  }
+  Ok(())
+}
+
+/// Moves the current local copy of `virtual_path` into the versions store, a no-op without versioning.
+/// Folders are archived file by file and left empty for the caller to remove.
+pub fn archive(dir: &Dirsync, virtual_path: &str) -> io::Result<()> {
+  let versioning = match &dir.versioning {
+    Some(versioning) => versioning,
+    None => return Ok(()),
+  };
+  let local_path = utils::side_path(&dir.local_path, virtual_path);
+
+  if fs::metadata(&local_path)?.is_dir() {
+    for entry in fs::read_dir(&local_path)? {
+      let name = entry?.file_name().to_string_lossy().to_string();
+      archive(dir, &utils::create_path(virtual_path, &name))?;
+    }
+    return Ok(());
+  }
+
+  let stamp = Local::now().format(STAMP_FORMAT).to_string();
+  let target = store_path(dir, versioning).join(format!("{}.{}", virtual_path, stamp));
+  if let Some(parent) = target.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  move_file(Path::new(&local_path), &target)?;
+  apply_retention(dir, versioning, virtual_path)
+}
+
+/// The version of `virtual_path` with `stamp`, the newest one for an empty stamp.
+pub fn find_version(dir: &Dirsync, virtual_path: &str, stamp: &str) -> io::Result<Version> {
+  let virtual_path = virtual_path.trim_matches('/');
+  list_versions(dir, virtual_path)?
+    .into_iter()
+    .filter(|version| version.virtual_path == virtual_path)
+    .find(|version| stamp.is_empty() || version.stamp == stamp)
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no version of {} found", virtual_path)))
+}
+
+/// Copies a version back in place, `stamp` picks one, the newest by default.
+/// The current copy is archived first so a restore can be undone.
+pub fn restore(dir: &Dirsync, virtual_path: &str, stamp: &str) -> io::Result<String> {
+  let virtual_path = virtual_path.trim_matches('/');
+  let version = find_version(dir, virtual_path, stamp)?;
+
+  let local_path = utils::side_path(&dir.local_path, virtual_path);
+  let part_path = utils::part_path(&local_path);
+  if let Some(parent) = Path::new(&local_path).parent() {
+    fs::create_dir_all(parent)?;
+  }
+  // copied first, archiving the current copy may drop the version by retention
+  fs::copy(&version.path, &part_path)?;
+  if Path::new(&local_path).exists() {
+    archive(dir, virtual_path)?;
+  }
+  fs::rename(&part_path, &local_path)?;
+  Ok(version.stamp.clone())
+}
+
+pub fn display_versions(dir: &Dirsync, filter: &str) {
+  println!("versions of {}:", dir.local_path);
+  match list_versions(dir, filter) {
+    Ok(versions) => {
+      for version in versions.iter() {
+        println!("  {}  {}  {} bytes", version.stamp, version.virtual_path, version.size);
+      }
+    },
+    Err(err) => eprintln!("Error: {}", err),
+  }
+}
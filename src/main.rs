@@ -4,132 +4,44 @@
  * MIT Licensed
  */
 
-use tokio;
+use clap::Parser;
 
-use crate::core::{args_parse, utils};
+use crate::core::{cli, commands, output};
 
 pub mod core {
     pub mod objects;
     pub mod config_file;
     pub mod file_conn;
     pub mod api_conn;
+    pub mod credentials;
+    pub mod local_conn;
+    pub mod remote;
     pub mod engine;
+    pub mod compare;
+    pub mod sync_state;
+    pub mod crypto;
+    pub mod compression;
+    pub mod versions;
+    pub mod trash;
+    pub mod backup;
+    pub mod restore;
+    pub mod verify;
+    pub mod status;
+    pub mod browse;
+    pub mod file_ops;
     pub mod utils;
-    pub mod args_parse;
+    pub mod output;
+    pub mod cli;
+    pub mod commands;
+    pub mod init;
 }
 
-
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let version_str = include_str!("./version.txt");
-    println!("k-cloud-sync-rust v{}", version_str);
-    let mut config_file: String = String::from("config.json");
-    let args_input = args_parse::get_args_input();
-    if args_input.file != "" {
-      config_file = args_input.file;
-    }
-
-    let exist_config_file: bool;
-    let exist_result = core::config_file::exists_file(&config_file);
-
-    match exist_result {
-        Ok(exist_config) => exist_config_file = exist_config,
-        Err(error) => {
-          println!("error verifying file existence {}", error);
-          return Ok(())
-        }
-    }
-    if !exist_config_file {
-      println!("config file {} does not exists",config_file);
-      return Ok(())
-    }
-
-    let config: core::objects::Configfile = core::config_file::load_config(&config_file).expect("Unable to load config: invalid file");
-
-    match args_input.action.as_str() {
-        "list" => {
-          utils::display_dirs_list(&config.dirs);
-          return Ok(())
-        },
-        "usage" => {
-          utils::display_help();
-          return Ok(())
-        },
-        "help" => {
-          utils::display_help();
-          return Ok(())
-        },
-        _ => {}
-    }
-
-    let base_url = config.base_url;
-    let api_key = config.api_key;
-    let dirs = config.dirs;
-    let user_name: String;
-    let user_id: String;
-    let session_id: String;
-    let authenticated:bool;
-
-    let api_client: core::api_conn::ApiClient = core::api_conn::ApiClient::new(&base_url, &api_key).expect("Unable to create api client");
-
-    println!("Authenticating...");
-    
-    match api_client.auth().await {
-        Ok(user) => {
-          authenticated = true;
-          user_name = user.username;
-          user_id = user.userId;
-          session_id = user.sessionId;
-        },
-        Err(err) => {
-          println!("Error: {}", err);
-          return Ok(())
-        },
-    }
-
-    let scopes_api = api_client.get_api_scopes().await.unwrap();
-    let has_permissions = utils::is_authorized(&scopes_api);
-    if !has_permissions {
-      println!("api key has not enough permissions");
-      return Ok(())
-    }
-
-    if authenticated {
-      println!("Authenticated as {}", user_name);
-      println!("User ID: {}", user_id);
-      println!("Session ID: {}", session_id);
-    } else {
-      println!("Not authenticated");
-      return Ok(());
-    }
-
-    println!("Syncing files...");
-
-    match args_input.mode.as_str() {
-      "all" => {
-        for dir in dirs.iter() {
-          match core::engine::sync_files(&dir, &api_client).await {
-            Ok(message) => println!("{}", message),
-            Err(err) => println!("Error: {}", err),
-          }
-        }
-      },
-      "single" => {
-        let dir = dirs.get(args_input.dir as usize);
-        match dir {
-            Some(dir_f) => {
-              match core::engine::sync_files(&dir_f, &api_client).await {
-                Ok(message) => println!("{}", message),
-                Err(err) => println!("Error: {}", err),
-              }
-            },
-            None => {
-              println!("this dir does not exixts");
-            }
-        }
-      },
-      _ => {}
-    }
+async fn main() {
+    let cli = cli::Cli::parse();
+    output::set_verbosity(cli.verbosity());
+    detail!("k-cloud-sync-rust v{}", cli::VERSION);
 
-    Ok(())
+    let status = commands::run(&cli).await;
+    std::process::exit(status);
 }